@@ -1,27 +1,659 @@
 #![feature(impl_trait_in_assoc_type)]
+#![feature(allocator_api)]
 #![allow(unused)]
+#![allow(clippy::needless_return)]
 use core::panic;
+use std::alloc::{Allocator, Global};
+use std::any::TypeId;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::hash::{BuildHasher, DefaultHasher, Hash, Hasher, RandomState};
+use std::fmt;
 use std::marker::PhantomData;
+use std::mem::MaybeUninit;
+use std::ops::{Deref, DerefMut, Range};
 
 /// Use like a pointer or index
-#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+///
+/// Handles are ordered by index first, then by generation, so a
+/// `BTreeMap<EntryHandle<T>, _>` iterates in arena layout order.
+/// The comparison traits are implemented by hand so they don't
+/// require anything of T.
 pub struct EntryHandle<T> {
     generation: u64,
     index: usize,
     enforce_typing: PhantomData<T>
 }
 
-#[derive(Debug)]
-struct GenVecEntry<T> {
-    generation: u64, // even means filled, odd means empty
-    data: T,
+impl<T> Clone for EntryHandle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for EntryHandle<T> {}
+
+impl<T> PartialEq for EntryHandle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.index == other.index && self.generation == other.generation
+    }
+}
+
+impl<T> Eq for EntryHandle<T> {}
+
+impl<T> fmt::Debug for EntryHandle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EntryHandle")
+            .field("generation", &self.generation)
+            .field("index", &self.index)
+            .finish()
+    }
+}
+
+impl<T> Hash for EntryHandle<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.index.hash(state);
+        self.generation.hash(state);
+    }
+}
+
+impl<T> PartialOrd for EntryHandle<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for EntryHandle<T> {
+    /// Index-primary ordering, see `cmp_by_index`.
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.cmp_by_index(other)
+    }
+}
+
+impl<T> EntryHandle<T> {
+    /// Rebuild a handle from parts stored elsewhere, e.g. after loading.
+    /// The parts are trusted: a handle that was never issued simply
+    /// fails to resolve, or resolves to whatever lives at that slot
+    /// and generation.
+    pub fn from_parts(index: usize, generation: u64) -> Self {
+        EntryHandle { generation, index, enforce_typing: PhantomData }
+    }
+    /// Index of the slot in the backing storage.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+    /// Generation of the slot when this handle was issued.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+    /// True if this handle resolves in that arena. A handle valid in
+    /// a GenVec stays valid in its clones until one of them frees or
+    /// reallocates the slot, so after that it can differ per copy.
+    pub fn is_valid_in<A: Allocator>(&self, genvec: &GenVec<T, A>) -> bool {
+        genvec.exists(*self)
+    }
+    /// Compare by index, then by generation.
+    /// This is the ordering used by Ord.
+    pub fn cmp_by_index(&self, other: &Self) -> Ordering {
+        self.index.cmp(&other.index)
+            .then(self.generation.cmp(&other.generation))
+    }
+    /// True if both handles refer to the same slot, regardless of
+    /// generation. A handle to a freed-then-reused slot is same_slot
+    /// with the old handle but not equal to it.
+    pub fn same_slot(&self, other: &Self) -> bool {
+        self.index == other.index
+    }
+    /// Wrap as a WeakHandle.
+    pub fn downgrade(self) -> WeakHandle<T> {
+        WeakHandle(self)
+    }
+    /// The same index and generation as a handle for another element
+    /// type, e.g. after GenVec::map. It's up to the caller that the
+    /// target arena actually mirrors the one this handle came from.
+    pub fn cast<U>(self) -> EntryHandle<U> {
+        return EntryHandle {
+            generation: self.generation,
+            index: self.index,
+            enforce_typing: PhantomData
+        };
+    }
+    /// Encode as a slotmap-style KeyData u64: version in the high 32
+    /// bits, index in the low 32 bits. slotmap versions are odd while
+    /// occupied (which also keeps them nonzero), so version is
+    /// generation + 1. Panics unless index fits in a u32 and generation
+    /// is below u32::MAX.
+    pub fn to_slotmap_keydata(&self) -> u64 {
+        let index = u32::try_from(self.index)
+            .unwrap_or_else(|_| panic!("Index too large for slotmap key: {:?}", (self.generation, self.index)));
+        let version = u32::try_from(self.generation + 1)
+            .unwrap_or_else(|_| panic!("Generation too large for slotmap key: {:?}", (self.generation, self.index)));
+        return ((version as u64) << 32) | index as u64;
+    }
+    /// Decode a slotmap-style KeyData u64, see to_slotmap_keydata.
+    /// Returns None if the version is not odd, i.e. not an occupied key.
+    pub fn from_slotmap_keydata(keydata: u64) -> Option<Self> {
+        let version = keydata >> 32;
+        if (version & 1) == 0 {
+            return None;
+        }
+        return Some(EntryHandle {
+            generation: version - 1,
+            index: (keydata & 0xffff_ffff) as usize,
+            enforce_typing: PhantomData
+        });
+    }
+    /// Erase the element type into id, normally TypeId::of::<T>(),
+    /// see ComponentHandle.
+    pub fn with_type_id(self, id: TypeId) -> ComponentHandle {
+        ComponentHandle { generation: self.generation, index: self.index, type_id: id }
+    }
+    /// Encode as 16 bytes for FFI or a custom wire format: generation
+    /// then index, each a little-endian u64.
+    pub fn write_bytes(&self, buf: &mut [u8; 16]) {
+        buf[..8].copy_from_slice(&self.generation.to_le_bytes());
+        buf[8..].copy_from_slice(&(self.index as u64).to_le_bytes());
+    }
+    /// Decode the layout written by write_bytes. Panics if the index
+    /// doesn't fit in a usize on this target.
+    pub fn read_bytes(buf: &[u8; 16]) -> Self {
+        let generation = u64::from_le_bytes(buf[..8].try_into().unwrap());
+        let index = u64::from_le_bytes(buf[8..].try_into().unwrap());
+        let index = usize::try_from(index)
+            .unwrap_or_else(|_| panic!("Index too large for this target: {:?}", (generation, index)));
+        return EntryHandle { generation, index, enforce_typing: PhantomData };
+    }
+}
+
+/// A handle that is expected to go stale, like a Weak is to an Rc.
+/// The same as an EntryHandle, but only resolves through upgrade, so
+/// the validity check is spelled out at each use.
+pub struct WeakHandle<T>(EntryHandle<T>);
+
+impl<T> Clone for WeakHandle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for WeakHandle<T> {}
+
+impl<T> PartialEq for WeakHandle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl<T> Eq for WeakHandle<T> {}
+
+impl<T> Hash for WeakHandle<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.hash(state);
+    }
+}
+
+impl<T> fmt::Debug for WeakHandle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("WeakHandle").field(&self.0).finish()
+    }
+}
+
+impl<T> From<EntryHandle<T>> for WeakHandle<T> {
+    fn from(h: EntryHandle<T>) -> Self {
+        WeakHandle(h)
+    }
+}
+
+impl<T> WeakHandle<T> {
+    /// Get a Some(&T) if the element is still alive, or None.
+    pub fn upgrade<'a, A: Allocator>(&self, genvec: &'a GenVec<T, A>) -> Option<&'a T> {
+        genvec.get_ref(self.0)
+    }
+    /// Get a Some(&mut T) if the element is still alive, or None.
+    pub fn upgrade_mut<'a, A: Allocator>(&self, genvec: &'a mut GenVec<T, A>) -> Option<&'a mut T> {
+        genvec.get_mut(self.0)
+    }
+    /// The underlying handle, whether or not it is still valid.
+    pub fn handle(&self) -> EntryHandle<T> {
+        self.0
+    }
+}
+
+/// A handle with its element type erased into a TypeId, e.g. to route
+/// it to the right arena in a registry of arenas by component type.
+/// See EntryHandle::with_type_id.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct ComponentHandle {
+    generation: u64,
+    index: usize,
+    type_id: TypeId,
+}
+
+impl ComponentHandle {
+    /// The id the handle was tagged with.
+    pub fn type_id(&self) -> TypeId {
+        self.type_id
+    }
+    /// The typed handle back, or None if the tag isn't U's TypeId.
+    pub fn try_into_handle<U: 'static>(self) -> Option<EntryHandle<U>> {
+        if self.type_id != TypeId::of::<U>() {
+            return None;
+        }
+        return Some(EntryHandle { generation: self.generation, index: self.index, enforce_typing: PhantomData });
+    }
+}
+
+/// How a handle relates to the arena, see GenVec::freshness.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Freshness {
+    /// The handle resolves.
+    Valid,
+    /// The slot exists but was freed or reallocated since.
+    Stale,
+    /// The index was never allocated in this arena.
+    OutOfRange,
+}
+
+/// What happens to a slot whose generation reaches the limit, see
+/// GenVec::set_generation_limit.
+///
+/// Retire never reuses a handle, at the cost of one dead slot per
+/// limit / 2 reuses of it. WrapWithEpoch keeps reusing the slot: its
+/// generation restarts above the arena's epoch, which only grows, so
+/// old handles still never validate. The part of a generation below
+/// the limit fits a small type, but the epoch adds to its magnitude,
+/// so only pack handles as (epoch, generation % limit) if the epoch
+/// is stored too.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum GenerationWrap {
+    /// Stop reusing the slot. It stays free and off the freelist, see
+    /// GenVec::reclaim_retired.
+    Retire,
+    /// Bump the arena epoch and reuse the slot.
+    WrapWithEpoch,
+}
+
+/// GenVec::resize would have dropped a live or reserved slot.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ResizeError {
+    pub generation: u64,
+    pub index: usize,
+}
+
+impl fmt::Display for ResizeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Slot in use past new length: {:?}", (self.generation, self.index))
+    }
+}
+
+impl std::error::Error for ResizeError {}
+
+/// The handle passed to GenVec::strict_free was already freed, or is
+/// from an older generation of its slot.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DoubleFree {
+    pub generation: u64,
+    pub index: usize,
+}
+
+impl fmt::Display for DoubleFree {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Double free: {:?}", (self.generation, self.index))
+    }
+}
+
+impl std::error::Error for DoubleFree {}
+
+/// The slots and freelist passed to GenVec::from_slots don't describe
+/// a consistent arena.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FromSlotsError {
+    /// A live slot has an odd generation, which is never live.
+    OddGeneration { generation: u64, index: usize },
+    /// A freelist entry is past the last slot.
+    FreelistOutOfRange { index: usize },
+    /// A freelist entry names a live slot.
+    LiveOnFreelist { generation: u64, index: usize },
+    /// An index is on the freelist more than once.
+    FreelistDuplicate { index: usize },
+    /// An empty slot is missing from the freelist, so it would never
+    /// be reused.
+    MissingFromFreelist { index: usize },
+}
+
+impl fmt::Display for FromSlotsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FromSlotsError::OddGeneration { generation, index } => write!(f, "Live slot with odd generation: {:?}", (generation, index)),
+            FromSlotsError::FreelistOutOfRange { index } => write!(f, "Freelist index out of bounds: {:?}", index),
+            FromSlotsError::LiveOnFreelist { generation, index } => write!(f, "Live slot on freelist: {:?}", (generation, index)),
+            FromSlotsError::FreelistDuplicate { index } => write!(f, "Index on freelist twice: {:?}", index),
+            FromSlotsError::MissingFromFreelist { index } => write!(f, "Empty slot not on freelist: {:?}", index),
+        }
+    }
+}
+
+impl std::error::Error for FromSlotsError {}
+
+/// A map passed to GenVec::from_handle_map can't be laid out as slots.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RebuildError {
+    /// Two handles name the same slot at different generations.
+    Conflict { index: usize, generations: (u64, u64) },
+    /// A handle has an odd generation, which is never live.
+    OddGeneration { generation: u64, index: usize },
+}
+
+impl fmt::Display for RebuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RebuildError::Conflict { index, generations } => write!(f, "Handles conflict at index {}: generations {:?}", index, generations),
+            RebuildError::OddGeneration { generation, index } => write!(f, "Live slot with odd generation: {:?}", (generation, index)),
+        }
+    }
+}
+
+impl std::error::Error for RebuildError {}
+
+/// Why GenVec::try_get_disjoint_mut failed. Positions index into the
+/// handles array that was passed in.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DisjointError {
+    /// Two handles point to the same slot.
+    Overlap { positions: (usize, usize) },
+    /// A handle doesn't resolve.
+    Stale { position: usize },
 }
 
+impl fmt::Display for DisjointError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DisjointError::Overlap { positions } => write!(f, "Overlapping handles at positions: {:?}", positions),
+            DisjointError::Stale { position } => write!(f, "Invalid handle at position: {:?}", position),
+        }
+    }
+}
+
+impl std::error::Error for DisjointError {}
+
 /// Use like a vec
-#[derive(Debug)]
-pub struct GenVec<T> {
-    vec: Vec<GenVecEntry<T>>,
-    freelist: Vec<usize>,
+///
+/// Zero-sized T such as () works and makes this a generational set:
+/// each slot then only costs its generation, sequence number and
+/// disabled flag.
+///
+/// Freed slots hold no value: free() drops it, and dropping the GenVec
+/// drops only the live values.
+///
+/// Generations, data and allocation sequence numbers are stored in
+/// parallel vecs, so scans that only check liveness don't stride over
+/// T, and an unfragmented arena's data is one contiguous &[T] (see
+/// as_contiguous_slice).
+///
+/// The slot vecs (including the disabled flags) and the freelist come
+/// from the allocator A, see new_in. The sparse side sets (reservations
+/// and the free audit) are std HashSets on the global heap.
+pub struct GenVec<T, A: Allocator = Global> {
+    generations: Vec<u64, A>, // even means filled, odd means empty
+    data: Vec<MaybeUninit<T>, A>, // initialized iff filled
+    seqs: Vec<u64, A>, // allocation sequence number of each live slot
+    next_seq: u64,
+    live: usize, // number of even generations, kept by fill_slot, push and take_slot
+    freelist: Vec<usize, A>,
+    reserved: HashSet<usize>, // free slots handed out by reserve_handle, not on the freelist
+    disabled: Vec<bool, A>, // live slots iter and iter_mut skip, see disable
+    generation_limit: Option<(u64, GenerationWrap)>,
+    epoch: u64, // wraps so far under GenerationWrap::WrapWithEpoch
+    retired: usize, // free slots off the freelist: GenerationWrap::Retire, or append-only
+    dense_prefix: usize, // slots below this are all live and enabled, so iter can skip its checks
+    fresh_generation: u64, // generation of newly pushed slots, above any truncated slot's
+    zero_on_free: bool,
+    freed: Option<HashSet<(u64, usize)>>, // (generation, index) of every handle freed, see set_free_audit
+    append_only: bool, // freed slots are retired, never reused
+    pinned: Option<usize>, // slot count growth may not pass, see pin_capacity
+    on_alloc: Option<HandleCallback<T>>,
+    on_free: Option<HandleCallback<T>>,
+}
+
+/// Debug callback invoked with a handle, see GenVec::set_on_alloc.
+/// Send + Sync so that setting one doesn't change whether the GenVec is.
+pub type HandleCallback<T> = Box<dyn FnMut(EntryHandle<T>) + Send + Sync>;
+
+/// Some(&data) if the generation says the slot is filled.
+fn filled<T>(generation: u64, data: &MaybeUninit<T>) -> Option<&T> {
+    if (generation & 1) != 0 {
+        return None;
+    }
+    // SAFETY: data is initialized while the generation is even
+    return Some(unsafe { data.assume_init_ref() });
+}
+
+fn filled_mut<T>(generation: u64, data: &mut MaybeUninit<T>) -> Option<&mut T> {
+    if (generation & 1) != 0 {
+        return None;
+    }
+    // SAFETY: data is initialized while the generation is even
+    return Some(unsafe { data.assume_init_mut() });
+}
+
+// Slot access by index
+impl<T, A: Allocator> GenVec<T, A> {
+    fn is_live(&self, index: usize) -> bool {
+        (self.generations[index] & 1) == 0
+    }
+    /// Some(&data) if the index is in range and live.
+    fn slot(&self, index: usize) -> Option<&T> {
+        return filled(*self.generations.get(index)?, &self.data[index]);
+    }
+    fn slot_mut(&mut self, index: usize) -> Option<&mut T> {
+        return filled_mut(*self.generations.get(index)?, &mut self.data[index]);
+    }
+    /// Like slot, but only if the slot is at this generation.
+    fn slot_at(&self, index: usize, generation: u64) -> Option<&T> {
+        if *self.generations.get(index)? != generation {
+            return None;
+        }
+        return self.slot(index);
+    }
+    fn slot_at_mut(&mut self, index: usize, generation: u64) -> Option<&mut T> {
+        if *self.generations.get(index)? != generation {
+            return None;
+        }
+        return self.slot_mut(index);
+    }
+    /// Make a free slot live with this value.
+    fn fill_slot(&mut self, index: usize, data: T) {
+        debug_assert!(!self.is_live(index));
+        self.data[index].write(data);
+        self.generations[index] += 1;
+        self.seqs[index] = self.bump_seq();
+        self.live += 1;
+        self.notify_alloc(index);
+    }
+    /// Add a live slot at the end.
+    fn push(&mut self, data: T) {
+        self.check_pinned(self.generations.len() + 1);
+        if self.dense_prefix == self.generations.len() {
+            self.dense_prefix += 1;
+        }
+        self.generations.push(self.fresh_generation);
+        self.data.push(MaybeUninit::new(data));
+        let seq = self.bump_seq();
+        self.seqs.push(seq);
+        self.disabled.push(false);
+        self.live += 1;
+        self.notify_alloc(self.generations.len() - 1);
+    }
+    /// Panic if growing to new_len slots would pass the pinned capacity.
+    fn check_pinned(&self, new_len: usize) {
+        if let Some(pinned) = self.pinned && new_len > pinned {
+            panic!("Capacity pinned: {:?}", (new_len, pinned));
+        }
+    }
+    /// Take the next allocation sequence number.
+    fn bump_seq(&mut self) -> u64 {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        return seq;
+    }
+    /// Make a live slot free, moving its value out.
+    fn take_slot(&mut self, index: usize) -> T {
+        debug_assert!(self.is_live(index));
+        self.dense_prefix = self.dense_prefix.min(index);
+        self.live -= 1;
+        self.disabled[index] = false;
+        if let Some(on_free) = &mut self.on_free {
+            on_free(EntryHandle { generation: self.generations[index], index, enforce_typing: PhantomData });
+        }
+        if let Some(freed) = &mut self.freed {
+            freed.insert((self.generations[index], index));
+        }
+        self.generations[index] += 1;
+        // SAFETY: the slot was live, and is now marked free so the
+        // value won't be read or dropped again
+        let data = unsafe { self.data[index].assume_init_read() };
+        if self.zero_on_free {
+            // Volatile so the dead store isn't optimized out
+            // SAFETY: the pointer comes from a &mut, so it is valid
+            unsafe { std::ptr::write_volatile(&mut self.data[index], MaybeUninit::zeroed()) };
+        }
+        return data;
+    }
+    /// Append free slots up to new_len, onto the freelist so the lowest
+    /// index is reused first, or retired if append-only.
+    fn grow_free(&mut self, new_len: usize) {
+        let old_len = self.generations.len();
+        if new_len <= old_len {
+            return;
+        }
+        self.check_pinned(new_len);
+        self.generations.resize(new_len, self.fresh_generation + 1);
+        self.data.resize_with(new_len, MaybeUninit::uninit);
+        self.seqs.resize(new_len, 0);
+        self.disabled.resize(new_len, false);
+        if self.append_only {
+            self.retired += new_len - old_len;
+        } else {
+            self.freelist.extend((old_len..new_len).rev());
+        }
+    }
+    /// Drop the slots from new_len on, which must all be free and not
+    /// reserved. Slots pushed later start above the dropped slots'
+    /// generations, so old handles to them stay stale.
+    fn truncate_free(&mut self, new_len: usize) {
+        if new_len >= self.generations.len() {
+            return;
+        }
+        let retired = (new_len..self.generations.len()).filter(|&index| self.is_retired(index)).count();
+        self.retired -= retired;
+        if let Some(&highest) = self.generations[new_len..].iter().max() {
+            // highest is odd, a free slot's
+            self.fresh_generation = self.fresh_generation.max(highest + 1);
+        }
+        self.generations.truncate(new_len);
+        self.data.truncate(new_len);
+        self.seqs.truncate(new_len);
+        self.disabled.truncate(new_len);
+        self.freelist.retain(|&index| index < new_len);
+    }
+    /// Recompute dense_prefix from the generations, after slots were
+    /// rearranged wholesale.
+    fn recount_dense_prefix(&mut self) {
+        self.dense_prefix = self.generations.iter()
+            .enumerate()
+            .take_while(|&(index, &generation)| (generation & 1) == 0 && !self.disabled[index])
+            .count();
+    }
+    /// Drop a reservation made by reserve_handle. Skips the generation
+    /// the reservation's handle was issued for, so it never resolves.
+    fn cancel_reserved(&mut self, index: usize) {
+        self.reserved.remove(&index);
+        self.generations[index] += 2;
+    }
+    /// One step of compaction: move live slot src down to the first
+    /// slot from dst on that isn't reserved or retired, and advance dst
+    /// past it. Returns (old, new) if it moved. Slots from dst up to
+    /// src must not be live.
+    fn compact_step(&mut self, src: usize, dst: &mut usize) -> Option<(EntryHandle<T>, EntryHandle<T>)> {
+        while self.reserved.contains(dst) || self.is_retired(*dst) {
+            *dst += 1;
+        }
+        let to = *dst;
+        *dst += 1;
+        if src == to {
+            return None;
+        }
+        let src_generation = self.generations[src];
+        let dst_generation = self.generations[to] + 1;
+        self.data.swap(src, to);
+        self.seqs.swap(src, to);
+        self.generations[to] = dst_generation;
+        self.generations[src] = src_generation + 1;
+        // Vacating can take the source slot to the limit; finish_compaction
+        // then skips it as retired, so count it here
+        if self.is_retired(src) {
+            self.retired += 1;
+        }
+        self.disabled.swap(src, to);
+        return Some((
+            EntryHandle { generation: src_generation, index: src, enforce_typing: PhantomData },
+            EntryHandle { generation: dst_generation, index: to, enforce_typing: PhantomData },
+        ));
+    }
+    /// Rebuild the freelist after compaction steps filled everything
+    /// below dst.
+    fn finish_compaction(&mut self, dst: usize) {
+        self.freelist.clear();
+        for index in (dst..self.generations.len()).rev() {
+            if !self.reserved.contains(&index) && !self.is_retired(index) {
+                self.release(index);
+            }
+        }
+        self.recount_dense_prefix();
+    }
+    /// Free a live slot and move its value out.
+    fn remove_at(&mut self, index: usize) -> T {
+        let data = self.take_slot(index);
+        self.release(index);
+        return data;
+    }
+    /// Whether a free slot was retired by GenerationWrap::Retire.
+    fn is_retired(&self, index: usize) -> bool {
+        if self.append_only {
+            return (self.generations[index] & 1) != 0 && !self.reserved.contains(&index);
+        }
+        if let Some((limit, GenerationWrap::Retire)) = self.generation_limit {
+            let generation = self.generations[index];
+            return (generation & 1) != 0 && generation % limit + 1 >= limit && !self.reserved.contains(&index);
+        }
+        return false;
+    }
+    /// Put a newly freed slot on the freelist, unless its generation
+    /// hit the limit or the arena is append-only.
+    fn release(&mut self, index: usize) {
+        if self.append_only {
+            self.retired += 1;
+            return;
+        }
+        if let Some((limit, wrap)) = self.generation_limit && self.generations[index] % limit + 1 >= limit {
+            match wrap {
+                GenerationWrap::Retire => {
+                    self.retired += 1;
+                    return;
+                }
+                GenerationWrap::WrapWithEpoch => {
+                    self.epoch += 1;
+                    self.generations[index] = self.epoch * limit + 1;
+                }
+            }
+        }
+        self.freelist.push(index);
+    }
+    fn notify_alloc(&mut self, index: usize) {
+        if let Some(on_alloc) = &mut self.on_alloc {
+            on_alloc(EntryHandle { generation: self.generations[index], index, enforce_typing: PhantomData });
+        }
+    }
 }
 
 // macro_rules! mkgetter {
@@ -40,10 +672,384 @@ impl<T> GenVec<T> {
         Self::with_capacity(8)
     }
     pub fn with_capacity(capacity: usize) -> Self {
+        Self::with_capacity_and_freelist(capacity, 0)
+    }
+    /// Like with_capacity, but also reserve room in the freelist so
+    /// the first wave of frees doesn't reallocate it.
+    pub fn with_capacity_and_freelist(data_cap: usize, free_cap: usize) -> Self {
+        Self::with_capacity_and_freelist_in(data_cap, free_cap, Global)
+    }
+    /// An arena that never reuses an index: free() still invalidates
+    /// handles, but the slot is retired instead of going on the
+    /// freelist, so indices only grow, in alloc order, e.g. to double
+    /// as timestamps. Freed slots keep taking up memory (though not
+    /// their values), and compaction leaves them in place.
+    pub fn append_only() -> Self {
+        let mut genvec = Self::new();
+        genvec.append_only = true;
+        return genvec;
+    }
+    /// Make an arena of count free slots for a loader to write with
+    /// init_slot and then bring live all at once with
+    /// assume_all_init, skipping the per-element alloc bookkeeping.
+    pub fn with_uninit(count: usize) -> Self {
+        let mut genvec = Self::with_capacity(count);
+        genvec.grow_free(count);
+        return genvec;
+    }
+    /// Consume both arenas into a new dense one holding all their
+    /// values in ascending key order, so iter() yields them sorted.
+    /// Equal keys keep index order, with self's values before other's.
+    pub fn merge_by_key<K: Ord>(mut self, mut other: Self, key: impl Fn(&T) -> K) -> GenVec<T> {
+        let mut merged = GenVec::with_capacity(self.len() + other.len());
+        let mut a = self.drain_sorted_by_key(&key).peekable();
+        let mut b = other.drain_sorted_by_key(&key).peekable();
+        loop {
+            let take_a = match (a.peek(), b.peek()) {
+                (Some(x), Some(y)) => key(x) <= key(y),
+                (Some(_), None) => true,
+                (None, Some(_)) => false,
+                (None, None) => break,
+            };
+            let data = if take_a { a.next() } else { b.next() };
+            merged.alloc(data.unwrap());
+        }
+        return merged;
+    }
+    /// Build an arena with each value live at its given index, at
+    /// generation 0, and every other index below the highest a free
+    /// slot. If an index repeats, the last value wins and the earlier
+    /// ones are dropped.
+    pub fn from_sparse(entries: impl IntoIterator<Item=(usize, T)>) -> Self {
+        let mut slots: Vec<Option<(u64, T)>> = Vec::new();
+        for (index, data) in entries {
+            if index >= slots.len() {
+                slots.resize_with(index + 1, || None);
+            }
+            slots[index] = Some((0, data));
+        }
+        let freelist = (0..slots.len()).rev().filter(|&i| slots[i].is_none()).collect();
+        return Self::from_slots(slots, freelist).unwrap();
+    }
+    /// The inverse of into_handle_map: an arena where each key resolves
+    /// to its value, and every other index below the highest key is a
+    /// free slot at generation 1, as with from_slots. Handles that went
+    /// stale before the map was made may resolve again once those free
+    /// slots are reused.
+    pub fn from_handle_map<S>(map: HashMap<EntryHandle<T>, T, S>) -> Result<Self, RebuildError> {
+        let mut slots: Vec<Option<(u64, T)>> = Vec::new();
+        for (h, data) in map {
+            if (h.generation & 1) != 0 {
+                return Err(RebuildError::OddGeneration { generation: h.generation, index: h.index });
+            }
+            if h.index >= slots.len() {
+                slots.resize_with(h.index + 1, || None);
+            }
+            if let Some((generation, _)) = &slots[h.index] {
+                return Err(RebuildError::Conflict { index: h.index, generations: (*generation, h.generation) });
+            }
+            slots[h.index] = Some((h.generation, data));
+        }
+        let freelist = (0..slots.len()).rev().filter(|&i| slots[i].is_none()).collect();
+        return Ok(Self::from_slots(slots, freelist).unwrap());
+    }
+    /// Rebuild an arena from its exact slot layout, e.g. when loading a
+    /// custom save format, so stored handles resolve again.
+    /// Each Some((generation, value)) is a live slot at that (even)
+    /// generation, each None an empty slot at generation 1.
+    /// The freelist must hold every empty slot exactly once and no live
+    /// one; its order is kept, so allocs reuse slots from its end.
+    pub fn from_slots(slots: Vec<Option<(u64, T)>>, freelist: Vec<usize>) -> Result<Self, FromSlotsError> {
+        let capacity = slots.len();
+        return Self::from_slots_with_capacity(slots, freelist, capacity);
+    }
+    /// Like from_slots, but with room for at least capacity slots, e.g.
+    /// the capacity() saved alongside the slots, so a reloaded arena
+    /// doesn't reallocate as soon as it grows again.
+    pub fn from_slots_with_capacity(slots: Vec<Option<(u64, T)>>, freelist: Vec<usize>, capacity: usize) -> Result<Self, FromSlotsError> {
+        let mut genvec = GenVec::with_capacity(capacity.max(slots.len()));
+        for (index, slot) in slots.into_iter().enumerate() {
+            match slot {
+                Some((generation, data)) => {
+                    if (generation & 1) != 0 {
+                        return Err(FromSlotsError::OddGeneration { generation, index });
+                    }
+                    genvec.generations.push(generation);
+                    genvec.data.push(MaybeUninit::new(data));
+                    let seq = genvec.bump_seq();
+                    genvec.seqs.push(seq);
+                    genvec.disabled.push(false);
+                    genvec.live += 1;
+                }
+                None => {
+                    genvec.generations.push(1);
+                    genvec.data.push(MaybeUninit::uninit());
+                    genvec.seqs.push(0);
+                    genvec.disabled.push(false);
+                }
+            }
+        }
+        let mut on_freelist = vec![false; genvec.generations.len()];
+        for &index in freelist.iter() {
+            if index >= on_freelist.len() {
+                return Err(FromSlotsError::FreelistOutOfRange { index });
+            }
+            if genvec.is_live(index) {
+                return Err(FromSlotsError::LiveOnFreelist { generation: genvec.generations[index], index });
+            }
+            if on_freelist[index] {
+                return Err(FromSlotsError::FreelistDuplicate { index });
+            }
+            on_freelist[index] = true;
+        }
+        if let Some(index) = (0..on_freelist.len()).find(|&index| !on_freelist[index] && !genvec.is_live(index)) {
+            return Err(FromSlotsError::MissingFromFreelist { index });
+        }
+        genvec.freelist = freelist;
+        genvec.recount_dense_prefix();
+        return Ok(genvec);
+    }
+}
+
+impl<T, A: Allocator + Clone> GenVec<T, A> {
+    /// Like new, but the slot vecs and freelist come from alloc,
+    /// e.g. a bump allocator for locality.
+    pub fn new_in(alloc: A) -> Self {
+        Self::with_capacity_in(8, alloc)
+    }
+    pub fn with_capacity_in(capacity: usize, alloc: A) -> Self {
+        Self::with_capacity_and_freelist_in(capacity, 0, alloc)
+    }
+    pub fn with_capacity_and_freelist_in(data_cap: usize, free_cap: usize, alloc: A) -> Self {
+        GenVec {
+            generations: Vec::with_capacity_in(data_cap, alloc.clone()),
+            data: Vec::with_capacity_in(data_cap, alloc.clone()),
+            seqs: Vec::with_capacity_in(data_cap, alloc.clone()),
+            disabled: Vec::with_capacity_in(data_cap, alloc.clone()),
+            next_seq: 0,
+            live: 0,
+            freelist: Vec::with_capacity_in(free_cap, alloc),
+            reserved: HashSet::new(),
+            generation_limit: None,
+            epoch: 0,
+            retired: 0,
+            dense_prefix: 0,
+            fresh_generation: 0,
+            zero_on_free: false,
+            freed: None,
+            append_only: false,
+            pinned: None,
+            on_alloc: None,
+            on_free: None,
+        }
+    }
+    /// Copy the arena with every live value passed through f, keeping
+    /// the slot layout: a handle into self, cast with EntryHandle::cast,
+    /// resolves to the mapped value. Callbacks aren't carried over.
+    pub fn map<U>(&self, mut f: impl FnMut(&T) -> U) -> GenVec<U, A> {
+        let mut data = Vec::with_capacity_in(self.data.len(), self.data.allocator().clone());
+        data.extend(self.generations.iter()
+            .zip(self.data.iter())
+            .map(|(&generation, item)| match filled(generation, item) {
+                Some(item) => MaybeUninit::new(f(item)),
+                None => MaybeUninit::uninit(),
+            }));
         GenVec {
-            vec: Vec::with_capacity(capacity),
-            freelist: Vec::new(),
+            generations: self.generations.clone(),
+            data,
+            seqs: self.seqs.clone(),
+            next_seq: self.next_seq,
+            live: self.live,
+            freelist: self.freelist.clone(),
+            reserved: self.reserved.clone(),
+            disabled: self.disabled.clone(),
+            generation_limit: self.generation_limit,
+            epoch: self.epoch,
+            retired: self.retired,
+            dense_prefix: self.dense_prefix,
+            fresh_generation: self.fresh_generation,
+            zero_on_free: self.zero_on_free,
+            freed: self.freed.clone(),
+            append_only: self.append_only,
+            // The copy's buffers are new, so there's nothing to pin
+            pinned: None,
+            on_alloc: None,
+            on_free: None,
+        }
+    }
+}
+
+impl<T, A: Allocator> GenVec<T, A> {
+    /// Get a handle to a slot without giving it a value yet, e.g. to hand
+    /// out handles before values are built in parallel. The handle
+    /// doesn't resolve until fill(handle, value).
+    pub fn reserve_handle(&mut self) -> EntryHandle<T> {
+        let index = match self.freelist.pop() {
+            Some(index) => index,
+            None => {
+                // A new slot, free
+                self.check_pinned(self.generations.len() + 1);
+                self.generations.push(self.fresh_generation + 1);
+                self.data.push(MaybeUninit::uninit());
+                self.seqs.push(0);
+                self.disabled.push(false);
+                self.generations.len() - 1
+            }
+        };
+        self.reserved.insert(index);
+        return EntryHandle {
+            generation: self.generations[index] + 1,
+            index,
+            enforce_typing: PhantomData
+        };
+    }
+    /// Like reserve_handle, but for a given index, e.g. to rebuild a
+    /// saved index layout. Grows the backing vec with free slots up to
+    /// index if needed. Returns the existing reservation's handle if
+    /// index is already reserved. Panics if index is live or retired.
+    pub fn ensure_index(&mut self, index: usize) -> EntryHandle<T> {
+        if index >= self.generations.len() {
+            self.grow_free(index + 1);
+            let pos = self.freelist.iter().position(|&i| i == index);
+            match pos {
+                Some(pos) => { self.freelist.remove(pos); }
+                None => self.retired -= 1, // append-only
+            }
+        } else if !self.reserved.contains(&index) {
+            let Some(pos) = self.freelist.iter().position(|&i| i == index) else {
+                panic!("Index not free: {:?}", (self.generations[index], index));
+            };
+            self.freelist.remove(pos);
+        }
+        self.reserved.insert(index);
+        return EntryHandle {
+            generation: self.generations[index] + 1,
+            index,
+            enforce_typing: PhantomData
+        };
+    }
+    /// reserve_handle() n times.
+    pub fn reserve_many(&mut self, n: usize) -> Vec<EntryHandle<T>> {
+        return (0..n).map(|_| self.reserve_handle()).collect();
+    }
+    /// Give a reserved handle its value, making it resolve. Gives the
+    /// value back if h isn't a pending reservation.
+    pub fn fill(&mut self, h: EntryHandle<T>, data: T) -> Result<(), T> {
+        if !self.reserved.contains(&h.index) || self.generations[h.index] + 1 != h.generation {
+            return Err(data);
+        }
+        self.reserved.remove(&h.index);
+        self.fill_slot(h.index, data);
+        return Ok(());
+    }
+    /// Roll back a reservation that hasn't been filled, freeing its
+    /// slot; the handle never resolves. Returns false if h isn't a
+    /// pending reservation, i.e. it was filled, cancelled or is stale.
+    pub fn cancel_reservation(&mut self, h: EntryHandle<T>) -> bool {
+        if !self.reserved.contains(&h.index) || self.generations[h.index] + 1 != h.generation {
+            return false;
+        }
+        self.cancel_reserved(h.index);
+        self.release(h.index);
+        return true;
+    }
+    /// Write a value into a free slot without making it live; see
+    /// assume_all_init. Writing the same slot twice leaks the first
+    /// value. Panics if the slot is live.
+    pub fn init_slot(&mut self, index: usize, data: T) {
+        if self.is_live(index) {
+            panic!("Index not free: {:?}", (self.generations[index], index));
+        }
+        self.data[index] = MaybeUninit::new(data);
+    }
+    /// Make every slot live, including reserved and retired ones, with
+    /// the value last written by init_slot.
+    ///
+    /// # Safety
+    /// Every slot that isn't live must have been written by init_slot
+    /// since it was last freed. Otherwise reading or dropping it reads
+    /// uninitialized memory.
+    pub unsafe fn assume_all_init(&mut self) {
+        self.freelist.clear();
+        self.reserved.clear();
+        self.retired = 0;
+        for index in 0..self.generations.len() {
+            if (self.generations[index] & 1) != 0 {
+                self.generations[index] += 1;
+                self.seqs[index] = self.bump_seq();
+                self.live += 1;
+                self.notify_alloc(index);
+            }
         }
+        self.recount_dense_prefix();
+    }
+    /// Call f with the handle of every newly allocated element, e.g. to
+    /// trace or assert invariants while debugging. None unsets it.
+    pub fn set_on_alloc(&mut self, f: Option<HandleCallback<T>>) {
+        self.on_alloc = f;
+    }
+    /// Call f with the handle of every freed element, just before it is
+    /// freed. None unsets it.
+    pub fn set_on_free(&mut self, f: Option<HandleCallback<T>>) {
+        self.on_free = f;
+    }
+    /// Overwrite a slot's bytes with zeros as it is freed, e.g. when T
+    /// holds secrets. Only the slot itself is scrubbed: memory T owns
+    /// (a String's buffer, say) is freed by its Drop as usual, and the
+    /// compiler may have left copies of the value elsewhere (registers,
+    /// the stack, moved-out values), so this narrows the window rather
+    /// than guaranteeing erasure. Compaction and shrinking don't scrub.
+    pub fn set_zero_on_free(&mut self, zero_on_free: bool) {
+        self.zero_on_free = zero_on_free;
+    }
+    /// Record every freed handle, for tests and debugging: was_freed
+    /// then tells a freed handle from a forged or mixed-up one, and
+    /// index_ref/index_mut panic with "Handle used after free". The
+    /// record grows with every free, so leave it off in release builds.
+    /// Turning it off forgets the record.
+    pub fn set_free_audit(&mut self, audit: bool) {
+        if !audit {
+            self.freed = None;
+        } else if self.freed.is_none() {
+            self.freed = Some(HashSet::new());
+        }
+    }
+    /// Reserve room for capacity slots and keep the backing storage
+    /// from moving, e.g. while FFI holds raw pointers into it. Until
+    /// unpin_capacity, anything that would push slots past capacity
+    /// panics instead (alloc, reserve_handle, resize, ...), and
+    /// try_alloc returns None. Calls that explicitly reallocate or
+    /// replace the storage aren't blocked: reserve, compact_and_shrink
+    /// and restore (which both reserve the pinned capacity again
+    /// afterwards) and swap_with (which moves the pin to other along
+    /// with the storage). Raw pointers don't survive them.
+    pub fn pin_capacity(&mut self, capacity: usize) {
+        let additional = capacity.saturating_sub(self.generations.len());
+        self.generations.reserve_exact(additional);
+        self.data.reserve_exact(additional);
+        self.seqs.reserve_exact(additional);
+        self.disabled.reserve_exact(additional);
+        self.pinned = Some(capacity);
+    }
+    /// Let the arena grow again, see pin_capacity.
+    pub fn unpin_capacity(&mut self) {
+        self.pinned = None;
+    }
+    /// Bound every slot's generation to below limit, e.g. to pack
+    /// handles into fewer bits. When a freed slot reaches it, wrap
+    /// decides whether the slot is retired or reused. None (the
+    /// default) lets generations grow to u64::MAX.
+    /// Panics if limit is odd or below 4: a limit of 2 would retire or
+    /// wrap every slot on its first free. Takes effect as slots are freed.
+    pub fn set_generation_limit(&mut self, limit: Option<(u64, GenerationWrap)>) {
+        if let Some((limit, _)) = limit && (limit < 4 || (limit & 1) != 0) {
+            panic!("Invalid generation limit: {:?}", limit);
+        }
+        self.generation_limit = limit;
+    }
+    /// How many times a generation wrapped, see GenerationWrap::WrapWithEpoch.
+    pub fn epoch(&self) -> u64 {
+        return self.epoch;
     }
     /// Allocate a new element, set its initial value (data),
     /// and get a handle to it.
@@ -52,12 +1058,13 @@ impl<T> GenVec<T> {
         let generation;
         if let Some(index_) = self.freelist.pop() {
             index = index_;
-            generation = self.vec[index].generation;
-            self.vec[index].data = data;
+            // Odd (free) to even (filled)
+            self.fill_slot(index, data);
+            generation = self.generations[index];
         } else {
-            index = self.vec.len();
-            generation = 0;
-            self.vec.push(GenVecEntry { generation, data });
+            index = self.generations.len();
+            generation = self.fresh_generation;
+            self.push(data);
         }
         return EntryHandle {
             generation,
@@ -65,109 +1072,1503 @@ impl<T> GenVec<T> {
             enforce_typing: PhantomData
         };
     }
-    /// Mark an element as disused. This does not call drop().
+    /// Like alloc, but None (dropping data) instead of growing past a
+    /// pinned capacity, see pin_capacity.
+    pub fn try_alloc(&mut self, data: T) -> Option<EntryHandle<T>> {
+        if self.freelist.is_empty() && self.pinned.is_some_and(|pinned| self.generations.len() >= pinned) {
+            return None;
+        }
+        return Some(self.alloc(data));
+    }
+    /// Allocate values at consecutive indices, in order, e.g. to keep a
+    /// fixed-size group of elements together. Always appends new slots,
+    /// skipping the freelist. None if the indices would overflow usize.
+    pub fn alloc_contiguous<const N: usize>(&mut self, values: [T; N]) -> Option<[EntryHandle<T>; N]> {
+        let start = self.generations.len();
+        self.check_pinned(start.checked_add(N)?);
+        self.reserve(N);
+        return Some(values.map(|data| {
+            let index = self.generations.len();
+            self.push(data);
+            EntryHandle { generation: self.generations[index], index, enforce_typing: PhantomData }
+        }));
+    }
+    /// Allocate every Ok value, or none of them: on the first Err the
+    /// values allocated so far are freed again (dropped, newest first)
+    /// and the error is returned. The freed slots' generations have
+    /// moved on, so handles seen through the alloc callback stay stale.
+    pub fn try_alloc_batch<E>(&mut self, items: impl IntoIterator<Item=Result<T, E>>) -> Result<Vec<EntryHandle<T>>, E> {
+        let mut handles = Vec::new();
+        for item in items {
+            match item {
+                Ok(data) => handles.push(self.alloc(data)),
+                Err(err) => {
+                    for &h in handles.iter().rev() {
+                        self.free(h);
+                    }
+                    return Err(err);
+                }
+            }
+        }
+        return Ok(handles);
+    }
+    /// Allocate into a specific freed index, e.g. to replay a recorded log.
+    /// Returns None if the index is live or out of range.
+    pub fn alloc_at(&mut self, index: usize, data: T) -> Option<EntryHandle<T>> {
+        if index >= self.generations.len() || self.is_live(index) {
+            return None;
+        }
+        let pos = self.freelist.iter().position(|&i| i == index)?;
+        self.freelist.remove(pos);
+        self.fill_slot(index, data);
+        return Some(EntryHandle {
+            generation: self.generations[index],
+            index,
+            enforce_typing: PhantomData
+        });
+    }
+    /// Mark an element as disused and drop its value.
     /// This invalidates the handle. Using the handle with
     /// the index_??? functions will panic. Using it with the
     /// get_ functions yields None.
     pub fn free(&mut self, h: EntryHandle<T>) {
         // Increase generation, add to free list
-        let el = &mut self.vec[h.index];
-        if el.generation != h.generation {
+        if self.generations[h.index] != h.generation || !self.is_live(h.index) {
             // panic!("Double free: {:?}", (h.generation, h.index));
             // eprintln!("Double free: {:?}", (h.generation, h.index));
             return;
         }
-        el.generation += 1;
-        self.freelist.push(h.index);
+        let data = self.remove_at(h.index);
+        // data is dropped here, after the slot is consistent
     }
-    /// Safely check if element exists.
-    pub fn exists(&self, h: EntryHandle<T>) -> bool {
-        if let Some(el) = self.vec.get(h.index) {
-            if el.generation == h.generation {
-                return true;
-            }
+    /// Like free(), but freeing an already-free or stale handle is an
+    /// error instead of a no-op. Useful for tracking down double frees.
+    /// A handle whose index is out of range is an error too.
+    pub fn strict_free(&mut self, h: EntryHandle<T>) -> Result<(), DoubleFree> {
+        if self.generations.get(h.index) != Some(&h.generation) || !self.is_live(h.index) {
+            return Err(DoubleFree { generation: h.generation, index: h.index });
         }
-        return false;
+        self.free(h);
+        return Ok(());
     }
-    /// Get a &T or panic.
-    pub fn index_ref(&self, h: EntryHandle<T>) -> &T {
-        let el = &self.vec[h.index];
-        if el.generation != h.generation {
-            panic!("Invalid handle: {:?}", (h.generation, h.index));
+    /// Free h and allocate new_value in its place, returning the new
+    /// handle; h goes stale. The slot is reused unless it was retired.
+    /// None, dropping new_value, if h isn't valid.
+    pub fn recycle(&mut self, h: EntryHandle<T>, new_value: T) -> Option<EntryHandle<T>> {
+        if !self.exists(h) {
+            return None;
         }
-        return &el.data;
+        self.free(h);
+        return Some(self.alloc(new_value));
     }
-    /// Get a &mut T or panic.
-    pub fn index_mut(&mut self, h: EntryHandle<T>) -> &mut T {
-        let el = &mut self.vec[h.index];
-        if el.generation != h.generation {
-            panic!("Invalid handle: {:?}", (h.generation, h.index));
-        }
-        return &mut el.data;
+    /// Number of live elements.
+    /// O(1): a cached count, which debug builds recount in iter() and
+    /// check_invariants recounts always.
+    pub fn len(&self) -> usize {
+        self.live
+    }
+    /// Free slots as counted by the freelist, reservations and retired
+    /// count, or None if they add up to more than there are slots.
+    fn free_slot_bookkeeping(&self) -> Option<usize> {
+        let free = self.freelist.len() + self.reserved.len() + self.retired;
+        return (free <= self.generations.len()).then_some(free);
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// Number of slots the backing vecs can hold without reallocating.
+    pub fn capacity(&self) -> usize {
+        self.generations.capacity().min(self.data.capacity())
+    }
+    /// How many more slots can be pushed before the backing vecs
+    /// reallocate. Reuse of free slots doesn't count against it.
+    pub fn spare_capacity(&self) -> usize {
+        self.capacity() - self.generations.len()
+    }
+    /// Bytes used by the arena: its own backing buffers plus
+    /// f(value) for each live value, where f gives the heap bytes a
+    /// value owns (e.g. String::capacity).
+    pub fn deep_memory_usage(&self, f: impl Fn(&T) -> usize) -> usize {
+        let backing = size_of::<Self>()
+            + self.generations.capacity() * size_of::<u64>()
+            + self.data.capacity() * size_of::<T>()
+            + self.seqs.capacity() * size_of::<u64>()
+            + self.disabled.capacity() * size_of::<bool>()
+            + self.freelist.capacity() * size_of::<usize>()
+            + self.reserved.capacity() * size_of::<usize>();
+        return backing + self.iter_with_handles().map(|(_, item)| f(item)).sum::<usize>();
+    }
+    /// Index the next alloc will reuse, or None if it will push a new
+    /// slot.
+    pub fn next_reuse_index(&self) -> Option<usize> {
+        self.freelist.last().copied()
+    }
+    /// Sort the freelist so the following allocs reuse free slots
+    /// lowest index first, e.g. for reproducible replays. Generations
+    /// and live data are untouched.
+    pub fn sort_freelist(&mut self) {
+        self.freelist.sort_unstable_by(|a, b| b.cmp(a));
+    }
+    /// Number of freed slots the next allocs will reuse before growing,
+    /// i.e. the freelist length. Backing capacity beyond high_water_mark
+    /// isn't counted.
+    pub fn free_count(&self) -> usize {
+        return self.freelist.len();
+    }
+    /// Number of backing slots, i.e. the highest index ever allocated + 1.
+    pub fn high_water_mark(&self) -> usize {
+        self.generations.len()
+    }
+    /// Highest currently live index, if any.
+    pub fn live_high_index(&self) -> Option<usize> {
+        self.generations.iter().rposition(|&generation| (generation & 1) == 0)
+    }
+    /// Reserve backing capacity for at least `additional` more slots in
+    /// every per-slot vec, so allocs up to it don't reallocate.
+    pub fn reserve(&mut self, additional: usize) {
+        self.generations.reserve(additional);
+        self.data.reserve(additional);
+        self.seqs.reserve(additional);
+        self.disabled.reserve(additional);
+    }
+    /// Grow the backing vecs to hold at least min slots in total,
+    /// rounded up to a power of two, for predictable allocation sizes.
+    /// Does nothing if they already hold that many.
+    pub fn reserve_pow2(&mut self, min: usize) {
+        let additional = min.next_power_of_two().saturating_sub(self.generations.len());
+        self.generations.reserve_exact(additional);
+        self.data.reserve_exact(additional);
+        self.seqs.reserve_exact(additional);
+        self.disabled.reserve_exact(additional);
+    }
+    /// Reserve room in the freelist for at least additional more free
+    /// slots, so a burst of frees doesn't reallocate it.
+    pub fn reserve_freelist(&mut self, additional: usize) {
+        self.freelist.reserve(additional);
+    }
+    /// h's index if h is valid, e.g. to index a side Vec kept in step
+    /// with the slots.
+    pub fn index_of(&self, h: EntryHandle<T>) -> Option<usize> {
+        return self.exists(h).then_some(h.index);
+    }
+    /// Hide a live element from iter and iter_mut (and so from for
+    /// loops), e.g. for an inactive entity, without freeing it. It keeps
+    /// its handle and value, still counts in len, and resolves through
+    /// get_ref and get_mut as before. Only iter and iter_mut skip it:
+    /// every other method that walks the live elements (copied,
+    /// cloned_values, count_where, the handle iterators, ...) still
+    /// sees it. Returns false if h isn't valid or is already disabled.
+    pub fn disable(&mut self, h: EntryHandle<T>) -> bool {
+        if !self.exists(h) || self.disabled[h.index] {
+            return false;
+        }
+        self.disabled[h.index] = true;
+        self.dense_prefix = self.dense_prefix.min(h.index);
+        return true;
+    }
+    /// Undo disable. Returns false if h isn't valid or isn't disabled.
+    /// Freeing an element also clears its flag.
+    pub fn enable(&mut self, h: EntryHandle<T>) -> bool {
+        if !self.is_disabled(h) {
+            return false;
+        }
+        self.disabled[h.index] = false;
+        return true;
+    }
+    /// True if h is valid and disabled.
+    pub fn is_disabled(&self, h: EntryHandle<T>) -> bool {
+        self.exists(h) && self.disabled[h.index]
+    }
+    /// Safely check if element exists.
+    pub fn exists(&self, h: EntryHandle<T>) -> bool {
+        if let Some(&generation) = self.generations.get(h.index) && generation == h.generation && (generation & 1) == 0 {
+            return true;
+        }
+        return false;
+    }
+    /// Decode each u64 from to_slotmap_keydata, e.g. as received over
+    /// the network, and keep it only if it names a live element here.
+    /// Malformed, stale and out-of-range values give None.
+    pub fn validate_raw(&self, raws: &[u64]) -> Vec<Option<EntryHandle<T>>> {
+        return raws.iter()
+            .map(|&raw| EntryHandle::from_slotmap_keydata(raw).filter(|&h| self.exists(h)))
+            .collect();
+    }
+    /// Like exists(), but tells a stale handle from an out-of-range one.
+    pub fn freshness(&self, h: EntryHandle<T>) -> Freshness {
+        if h.index >= self.generations.len() {
+            return Freshness::OutOfRange;
+        }
+        if self.exists(h) {
+            return Freshness::Valid;
+        }
+        return Freshness::Stale;
+    }
+    /// Whether h was freed while set_free_audit was on. A handle that
+    /// resolves again after restore isn't reported, so index_ref and
+    /// index_mut still accept it.
+    pub fn was_freed(&self, h: EntryHandle<T>) -> bool {
+        if self.exists(h) {
+            return false;
+        }
+        return self.freed.as_ref().is_some_and(|freed| freed.contains(&(h.generation, h.index)));
+    }
+    /// exists() for each handle, in order.
+    pub fn validate_all(&self, handles: &[EntryHandle<T>]) -> Vec<bool> {
+        handles.iter().map(|&h| self.exists(h)).collect()
+    }
+    /// Get a &T or panic.
+    pub fn index_ref(&self, h: EntryHandle<T>) -> &T {
+        if self.was_freed(h) {
+            panic!("Handle used after free: {:?}", (h.generation, h.index));
+        }
+        if let Some(data) = self.slot_at(h.index, h.generation) {
+            return data;
+        }
+        panic!("Invalid handle: {:?}", (h.generation, h.index));
+    }
+    /// Get a &mut T or panic.
+    #[allow(clippy::should_implement_trait)] // takes a handle, not an IndexMut index
+    pub fn index_mut(&mut self, h: EntryHandle<T>) -> &mut T {
+        if self.was_freed(h) {
+            panic!("Handle used after free: {:?}", (h.generation, h.index));
+        }
+        if let Some(data) = self.slot_at_mut(h.index, h.generation) {
+            return data;
+        }
+        panic!("Invalid handle: {:?}", (h.generation, h.index));
+    }
+    /// Get a &T by backing index, ignoring generation, or panic if the
+    /// slot isn't live. This gives up the generational check; only use
+    /// it where indices are known to be stable.
+    pub fn raw_index(&self, index: usize) -> &T {
+        if let Some(data) = self.slot(index) {
+            return data;
+        }
+        panic!("Invalid index: {:?}", index);
+    }
+    /// Get a &mut T by backing index, see raw_index.
+    pub fn raw_index_mut(&mut self, index: usize) -> &mut T {
+        if let Some(data) = self.slot_mut(index) {
+            return data;
+        }
+        panic!("Invalid index: {:?}", index);
     }
     // mkgetter!(get_mut, mut);
     // mkgetter!(get_ref);
     
     /// Get a Some(&T) or None.
     pub fn get_ref(&self, h: EntryHandle<T>) -> Option<&T> {
-        if self.vec[h.index].generation != h.generation {
-            return None;
-        }
-        return Some(&self.vec[h.index].data);
+        return self.slot_at(h.index, h.generation);
     }
     /// Get a Some(&mut T) or None.
     pub fn get_mut(&mut self, h: EntryHandle<T>) -> Option<&mut T> {
-        if self.vec[h.index].generation != h.generation {
+        return self.slot_at_mut(h.index, h.generation);
+    }
+    /// Like get_mut, but wrapped in a Guard that keeps the handle next
+    /// to the borrow. While the guard lives the arena is borrowed, so
+    /// h can't go stale.
+    pub fn borrow_mut(&mut self, h: EntryHandle<T>) -> Option<Guard<'_, T>> {
+        let generation = self.generations.get(h.index)?;
+        if *generation != h.generation {
             return None;
         }
-        return Some(&mut self.vec[h.index].data);
+        let value = filled_mut(h.generation, &mut self.data[h.index])?;
+        return Some(Guard { value, generation, handle: h });
+    }
+    /// Get the &T, or default if h is invalid, e.g. a placeholder for
+    /// a despawned entity.
+    pub fn get_ref_or<'a>(&'a self, h: EntryHandle<T>, default: &'a T) -> &'a T {
+        return self.get_ref(h).unwrap_or(default);
     }
     
+    /// Call f on the value of each valid handle in handles, in order,
+    /// skipping invalid ones. A handle listed twice gets f twice.
+    pub fn for_handles_mut(&mut self, handles: &[EntryHandle<T>], mut f: impl FnMut(&mut T)) {
+        for &h in handles {
+            if let Some(item) = self.get_mut(h) {
+                f(item);
+            }
+        }
+    }
+    /// True if both handles are valid and point to different slots,
+    /// i.e. borrowing both mutably at once wouldn't alias.
+    pub fn are_disjoint_live(&self, a: EntryHandle<T>, b: EntryHandle<T>) -> bool {
+        a.index != b.index && self.exists(a) && self.exists(b)
+    }
+    /// Borrow several elements mutably at once. Fails if any handle is
+    /// invalid, or two handles point to the same slot.
+    pub fn try_get_disjoint_mut<const N: usize>(&mut self, handles: [EntryHandle<T>; N]) -> Result<[&mut T; N], DisjointError> {
+        for (position, h) in handles.iter().enumerate() {
+            if !self.exists(*h) {
+                return Err(DisjointError::Stale { position });
+            }
+            if let Some(first) = handles[..position].iter().position(|other| other.index == h.index) {
+                return Err(DisjointError::Overlap { positions: (first, position) });
+            }
+        }
+        let data = self.data.as_mut_ptr();
+        // SAFETY: every slot is live and distinct, so the borrows are
+        // initialized and don't alias
+        return Ok(handles.map(|h| unsafe { (*data.add(h.index)).assume_init_mut() }));
+    }
+    /// try_get_disjoint_mut without the reason for failing.
+    pub fn get_disjoint_mut<const N: usize>(&mut self, handles: [EntryHandle<T>; N]) -> Option<[&mut T; N]> {
+        return self.try_get_disjoint_mut(handles).ok();
+    }
     /// Get an iterator yields &items.
     /// O(n) over highest number of elements ever in use, not counting underlying vec unused capacity.
+    /// The leading run of slots that were never freed (or that compact
+    /// filled) is walked as a plain slice, without checking generations.
+    /// Skips disabled elements, see disable.
     pub fn iter(&self) -> impl Iterator<Item=&T> + '_ {
-        self.vec.iter()
-            .filter_map(
-                |item| ((item.generation & 1) == 0).then_some(&item.data)
-            )
+        // Catches a path that forgot to update the cached count
+        debug_assert_eq!(self.live, self.generations.iter().filter(|&&generation| (generation & 1) == 0).count(),
+            "Live count drifted from generations");
+        let (dense, rest) = self.data.split_at(self.dense_prefix);
+        // SAFETY: every slot below dense_prefix is live
+        let dense = unsafe { std::slice::from_raw_parts(dense.as_ptr() as *const T, dense.len()) };
+        let start = self.dense_prefix;
+        dense.iter().chain(
+            self.generations[start..].iter()
+                .zip(rest.iter())
+                .zip(self.disabled[start..].iter())
+                .filter(|&(_, &disabled)| !disabled)
+                .filter_map(|((&generation, item), _)| filled(generation, item))
+        )
     }
     /// Get an iterator yields &mut items.
     /// O(n) over highest number of elements ever in use, not counting underlying vec unused capacity.
+    /// Takes the same fast path as iter, and also skips disabled elements.
     pub fn iter_mut(&mut self) -> impl Iterator<Item=&mut T> + '_ {
-        self.vec.iter_mut()
+        let (dense, rest) = self.data.split_at_mut(self.dense_prefix);
+        // SAFETY: every slot below dense_prefix is live
+        let dense = unsafe { std::slice::from_raw_parts_mut(dense.as_mut_ptr() as *mut T, dense.len()) };
+        let start = self.dense_prefix;
+        dense.iter_mut().chain(
+            self.generations[start..].iter()
+                .zip(rest.iter_mut())
+                .zip(self.disabled[start..].iter())
+                .filter(|&(_, &disabled)| !disabled)
+                .filter_map(|((&generation, item), _)| filled_mut(generation, item))
+        )
+    }
+    /// Get an iterator yields (seq, handle, &item) for live elements,
+    /// seq being the element's allocation sequence number: it counts
+    /// allocs from 0 and is never reused, so a reused slot gets a new
+    /// one. Compaction keeps each element's seq. In index order.
+    pub fn iter_with_seq(&self) -> impl Iterator<Item=(u64, EntryHandle<T>, &T)> + '_ {
+        self.iter_with_handles().map(|(h, item)| (self.seqs[h.index], h, item))
+    }
+    /// The sequence number the next alloc will get, see iter_with_seq.
+    pub fn next_seq(&self) -> u64 {
+        return self.next_seq;
+    }
+    /// How many allocations happened after h's element was allocated,
+    /// i.e. its age in sequence numbers (see next_seq), e.g. to expire
+    /// old entries. 0 for the newest element, None if h isn't valid.
+    pub fn age(&self, h: EntryHandle<T>) -> Option<u64> {
+        if !self.exists(h) {
+            return None;
+        }
+        // The latest allocation has seq next_seq - 1
+        return Some(self.next_seq - 1 - self.seqs[h.index]);
+    }
+    /// How many elements were ever allocated, counting every way a slot
+    /// becomes live (alloc, fill, loading with from_slots, ...) but not
+    /// moves by compaction. Never goes down, e.g. to measure churn.
+    pub fn total_allocations(&self) -> u64 {
+        return self.next_seq;
+    }
+    /// Get an iterator yields sliding windows of n live items, like
+    /// slice::windows. Freed slots are skipped, so neighbours in a
+    /// window are consecutive in live order. Panics if n is 0.
+    pub fn live_windows(&self, n: usize) -> impl Iterator<Item=Vec<&T>> + '_ {
+        if n == 0 {
+            panic!("Window size must be nonzero");
+        }
+        let live: Vec<&T> = self.iter_with_handles().map(|(_, item)| item).collect();
+        let count = (live.len() + 1).saturating_sub(n);
+        return (0..count).map(move |start| live[start..start + n].to_vec());
+    }
+    /// Call f with each live (handle, &mut item) in index order,
+    /// stopping at and returning the first error.
+    pub fn try_for_each_mut<E>(&mut self, mut f: impl FnMut(EntryHandle<T>, &mut T) -> Result<(), E>) -> Result<(), E> {
+        for (index, (&generation, item)) in self.generations.iter().zip(self.data.iter_mut()).enumerate() {
+            if let Some(item) = filled_mut(generation, item) {
+                f(EntryHandle { generation, index, enforce_typing: PhantomData }, item)?;
+            }
+        }
+        return Ok(());
+    }
+    /// Get an iterator yields (handle, &mut item) for live slots from
+    /// from's index on, or from the start if None, e.g. to continue a
+    /// time-budgeted pass next frame. from doesn't need to be valid
+    /// any more, only its index is used.
+    pub fn resume_iter_mut(&mut self, from: Option<EntryHandle<T>>) -> impl Iterator<Item=(EntryHandle<T>, &mut T)> + '_ {
+        let start = from.map_or(0, |h| h.index);
+        self.generations.iter()
+            .zip(self.data.iter_mut())
+            .enumerate()
+            .skip(start)
+            .filter_map(
+                |(index, (&generation, item))| Some((
+                    EntryHandle { generation, index, enforce_typing: PhantomData },
+                    filled_mut(generation, item)?
+                ))
+            )
+    }
+    /// Call f on up to budget live elements, starting at resume's index
+    /// (the start if None), and set resume to the next unprocessed
+    /// element's handle, or None once the end is reached, e.g. to
+    /// spread a pass over frames. See resume_iter_mut.
+    pub fn iterate_budget(&mut self, budget: usize, mut f: impl FnMut(&mut T), resume: &mut Option<EntryHandle<T>>) {
+        let mut index = resume.map_or(0, |h| h.index);
+        let mut done = 0;
+        *resume = None;
+        while index < self.generations.len() {
+            if let Some(item) = self.slot_mut(index) {
+                if done == budget {
+                    *resume = self.handle_at(index);
+                    return;
+                }
+                f(item);
+                done += 1;
+            }
+            index += 1;
+        }
+    }
+    /// Call f with a shared context and each live &mut item in index
+    /// order, e.g. to update entities from a read-only world resource.
+    pub fn for_each_mut_with<C: ?Sized>(&mut self, ctx: &C, mut f: impl FnMut(&C, &mut T)) {
+        for (&generation, item) in self.generations.iter().zip(self.data.iter_mut()) {
+            if let Some(item) = filled_mut(generation, item) {
+                f(ctx, item);
+            }
+        }
+    }
+    /// Live handles in index order, for `for h in genvec.handles_view()`.
+    /// To free while walking, collect the handles first.
+    pub fn handles_view(&self) -> HandlesView<'_, T, A> {
+        HandlesView { genvec: self }
+    }
+    /// Get an iterator yields (handle, &item) pairs.
+    /// O(n) over highest number of elements ever in use, not counting underlying vec unused capacity.
+    pub fn iter_with_handles(&self) -> impl Iterator<Item=(EntryHandle<T>, &T)> + '_ {
+        self.generations.iter()
+            .zip(self.data.iter())
+            .enumerate()
+            .filter_map(
+                |(index, (&generation, item))| Some((
+                    EntryHandle { generation, index, enforce_typing: PhantomData },
+                    filled(generation, item)?
+                ))
+            )
+    }
+    /// Get an iterator yields (handle, row, &item) for live elements,
+    /// where row counts live elements from 0, e.g. for a UI list with
+    /// contiguous row numbers and a stable id per row.
+    pub fn ui_rows(&self) -> impl Iterator<Item=(EntryHandle<T>, usize, &T)> + '_ {
+        self.iter_with_handles().enumerate().map(|(row, (h, item))| (h, row, item))
+    }
+    /// iter_with_handles() with peek(), e.g. to look at the next token
+    /// and its handle before deciding whether to consume it.
+    pub fn live_peekable(&self) -> std::iter::Peekable<impl Iterator<Item=(EntryHandle<T>, &T)> + '_> {
+        self.iter_with_handles().peekable()
+    }
+    /// For each index, the handle of its slot if it is live, e.g. to
+    /// rebuild handles from a recorded list of indices.
+    pub fn handles_for_indices(&self, indices: &[usize]) -> Vec<Option<EntryHandle<T>>> {
+        indices.iter().map(|&index| self.handle_at(index)).collect()
+    }
+    /// Get an iterator yields handles to live slots with an index in
+    /// range, e.g. to process one shard of the arena. The range is
+    /// clamped to the slots that exist.
+    pub fn handles_in_range(&self, range: Range<usize>) -> impl Iterator<Item=EntryHandle<T>> + '_ {
+        let end = range.end.min(self.generations.len());
+        let start = range.start.min(end);
+        (start..end).filter_map(|index| self.handle_at(index))
+    }
+    /// Number of live slots with an index in range, clamped like
+    /// handles_in_range, e.g. to balance shards.
+    pub fn count_in_range(&self, range: Range<usize>) -> usize {
+        let end = range.end.min(self.generations.len());
+        let start = range.start.min(end);
+        return self.generations[start..end].iter().filter(|&&generation| (generation & 1) == 0).count();
+    }
+    /// Get an iterator yields (index, &item) for live slots, for when
+    /// the generation isn't needed.
+    pub fn enumerate_live(&self) -> impl Iterator<Item=(usize, &T)> + '_ {
+        self.generations.iter()
+            .zip(self.data.iter())
+            .enumerate()
+            .filter_map(|(index, (&generation, item))| Some((index, filled(generation, item)?)))
+    }
+    /// Like iter_with_handles, but from the highest index to the lowest,
+    /// e.g. to visit the most recently pushed elements first.
+    pub fn iter_with_handles_rev(&self) -> impl Iterator<Item=(EntryHandle<T>, &T)> + '_ {
+        self.generations.iter()
+            .zip(self.data.iter())
+            .enumerate()
+            .rev()
+            .filter_map(
+                |(index, (&generation, item))| Some((
+                    EntryHandle { generation, index, enforce_typing: PhantomData },
+                    filled(generation, item)?
+                ))
+            )
+    }
+    /// Borrow all data as one slice, if there are no holes (no slot is
+    /// free). Checking this scans the generations only.
+    pub fn as_contiguous_slice(&self) -> Option<&[T]> {
+        if !self.freelist.is_empty() || !self.generations.iter().all(|&generation| (generation & 1) == 0) {
+            return None;
+        }
+        // SAFETY: every slot is filled, so every element is initialized,
+        // and MaybeUninit<T> has the same layout as T
+        return Some(unsafe { std::slice::from_raw_parts(self.data.as_ptr().cast::<T>(), self.data.len()) });
+    }
+    /// Get an iterator over each maximal run of live slots as one
+    /// &mut [T], in index order, e.g. for SIMD. Free slots split runs.
+    pub fn live_runs_mut(&mut self) -> impl Iterator<Item=&mut [T]> + '_ {
+        let generations = &self.generations[..];
+        let mut rest = &mut self.data[..];
+        let mut index = 0;
+        std::iter::from_fn(move || {
+            while index < generations.len() && (generations[index] & 1) != 0 {
+                rest = &mut std::mem::take(&mut rest)[1..];
+                index += 1;
+            }
+            let start = index;
+            while index < generations.len() && (generations[index] & 1) == 0 {
+                index += 1;
+            }
+            if start == index {
+                return None;
+            }
+            let (run, tail) = std::mem::take(&mut rest).split_at_mut(index - start);
+            rest = tail;
+            // SAFETY: every slot in run is filled, so initialized, and
+            // MaybeUninit<T> has the same layout as T
+            return Some(unsafe { std::slice::from_raw_parts_mut(run.as_mut_ptr().cast::<T>(), run.len()) });
+        })
+    }
+    /// Get an iterator over every backing slot, live or not, as
+    /// (index, generation, Some(&item) if live). For introspection.
+    pub fn raw_slots(&self) -> impl Iterator<Item=(usize, u64, Option<&T>)> + '_ {
+        self.generations.iter()
+            .zip(self.data.iter())
+            .enumerate()
+            .map(|(index, (&generation, item))| (index, generation, filled(generation, item)))
+    }
+    
+    /// Collect every currently valid handle. Slow, meant for debugging,
+    /// e.g. asserting that a handle was or wasn't freed.
+    pub fn live_handle_set(&self) -> HashSet<EntryHandle<T>> {
+        self.iter_with_handles().map(|(h, _)| h).collect()
+    }
+    /// Panic with the live (handle, index) pairs if any element is
+    /// still allocated. For asserting a test cleaned up after itself.
+    pub fn assert_all_freed(&self) {
+        let leaked: Vec<_> = self.iter_with_handles().map(|(h, _)| (h, h.index)).collect();
+        if !leaked.is_empty() {
+            panic!("Leaked handles: {:?}", leaked);
+        }
+    }
+    /// Check the internal bookkeeping, e.g. in a fuzz harness.
+    /// Describes the first violation found.
+    pub fn check_invariants(&self) -> Result<(), String> {
+        let len = self.generations.len();
+        if self.data.len() != len || self.seqs.len() != len || self.disabled.len() != len {
+            return Err(format!("Data length differs from slot count: {:?}", (self.data.len(), self.seqs.len(), self.disabled.len(), len)));
+        }
+        let mut seen = vec![false; len];
+        for &index in self.freelist.iter() {
+            if index >= len {
+                return Err(format!("Freelist index out of bounds: {:?}", index));
+            }
+            if (self.generations[index] & 1) == 0 {
+                return Err(format!("Live slot on freelist: {:?}", (self.generations[index], index)));
+            }
+            if seen[index] {
+                return Err(format!("Index on freelist twice: {:?}", index));
+            }
+            seen[index] = true;
+        }
+        for &index in self.reserved.iter() {
+            if index >= len || (self.generations[index] & 1) == 0 || seen[index] {
+                return Err(format!("Reserved index not a free slot off the freelist: {:?}", index));
+            }
+        }
+        let live = self.generations.iter().filter(|&&generation| (generation & 1) == 0).count();
+        if live != self.live {
+            return Err(format!("Live count differs from len(): {:?}", (live, self.live)));
+        }
+        if self.free_slot_bookkeeping() != Some(len - live) {
+            return Err(format!("Free slots unaccounted for: {:?}", (len - live, self.freelist.len(), self.reserved.len(), self.retired)));
+        }
+        if self.dense_prefix > len || self.generations[..self.dense_prefix].iter().any(|&generation| (generation & 1) != 0) {
+            return Err(format!("Free slot below dense prefix: {:?}", self.dense_prefix));
+        }
+        for index in (0..len).filter(|&index| self.disabled[index]) {
+            if (self.generations[index] & 1) != 0 || index < self.dense_prefix {
+                return Err(format!("Disabled index not a live slot above the dense prefix: {:?}", index));
+            }
+        }
+        return Ok(());
+    }
+    /// Panic if a slot the freelist or a reservation treats as free has
+    /// an even (live) generation. Liveness is the generation's parity,
+    /// so this is the one way a slot's parity can disagree with its
+    /// state, e.g. after a bookkeeping bug freed a slot twice; an alloc would
+    /// then reuse a live slot and its handle would alias the old one.
+    /// A cheaper, panicking subset of check_invariants.
+    pub fn assert_no_generation_collision(&self) {
+        for &index in self.freelist.iter().chain(self.reserved.iter()) {
+            if self.is_live(index) {
+                panic!("Live slot marked free: {:?}", (self.generations[index], index));
+            }
+        }
+    }
+    /// Compare live handles with an earlier copy of this GenVec, e.g. one
+    /// from clone(). Returns (added, removed): handles live now but not
+    /// then, and live then but not now, in index order.
+    pub fn changed_handles<B: Allocator>(&self, previous: &GenVec<T, B>) -> (Vec<EntryHandle<T>>, Vec<EntryHandle<T>>) {
+        let mut added = Vec::new();
+        let mut removed = Vec::new();
+        for index in 0..self.generations.len().max(previous.generations.len()) {
+            let now = self.handle_at(index);
+            let then = previous.handle_at(index);
+            if now == then {
+                continue;
+            }
+            added.extend(now);
+            removed.extend(then);
+        }
+        return (added, removed);
+    }
+    
+    /// Collect live (handle, &item) pairs sorted by a key, e.g. depth or priority.
+    /// The sort is stable, so equal keys keep index order.
+    pub fn values_sorted_by_key<K: Ord>(&self, f: impl Fn(&T) -> K) -> Vec<(EntryHandle<T>, &T)> {
+        let mut items: Vec<_> = self.iter_with_handles().collect();
+        items.sort_by_key(|(_, item)| f(item));
+        return items;
+    }
+    /// Handle to the first live element (lowest index) for which f
+    /// returns true. A linear scan, for lookups without a side map.
+    pub fn find(&self, f: impl Fn(&T) -> bool) -> Option<EntryHandle<T>> {
+        self.iter_with_handles().find(|(_, item)| f(item)).map(|(h, _)| h)
+    }
+    /// Handles to every live element for which f returns true, in index order.
+    pub fn find_all(&self, f: impl Fn(&T) -> bool) -> Vec<EntryHandle<T>> {
+        self.iter_with_handles().filter(|(_, item)| f(item)).map(|(h, _)| h).collect()
+    }
+    /// All live handles in a pseudo-random order that depends only on
+    /// seed and the live set, e.g. for fair turn order or sampling.
+    /// Uses a xorshift64 Fisher-Yates shuffle; not for cryptography.
+    pub fn shuffled_handles(&self, seed: u64) -> Vec<EntryHandle<T>> {
+        let mut handles: Vec<_> = self.iter_with_handles().map(|(h, _)| h).collect();
+        // xorshift gets stuck at 0
+        let mut state = (seed ^ 0x9e37_79b9_7f4a_7c15).max(1);
+        for i in (1..handles.len()).rev() {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            handles.swap(i, (state % (i as u64 + 1)) as usize);
+        }
+        return handles;
+    }
+    /// Get an iterator yields (handle, &item) for live elements for
+    /// which f returns true.
+    pub fn filtered_handles<F: Fn(&T) -> bool>(&self, f: F) -> impl Iterator<Item=(EntryHandle<T>, &T)> {
+        self.iter_with_handles().filter(move |(_, item)| f(item))
+    }
+    /// Collect the live elements into groups keyed by f, each group in
+    /// index order.
+    pub fn group_by_key<K: Eq + Hash>(&self, f: impl Fn(&T) -> K) -> HashMap<K, Vec<(EntryHandle<T>, &T)>> {
+        let mut groups: HashMap<K, Vec<_>> = HashMap::new();
+        for (h, item) in self.iter_with_handles() {
+            groups.entry(f(item)).or_default().push((h, item));
+        }
+        return groups;
+    }
+    /// Fold over live elements in index order, passing each handle,
+    /// e.g. to find which element contributes the maximum.
+    pub fn fold_with_handles<B>(&self, init: B, mut f: impl FnMut(B, EntryHandle<T>, &T) -> B) -> B {
+        self.iter_with_handles().fold(init, |acc, (h, item)| f(acc, h, item))
+    }
+    /// Count the live elements for which f returns true.
+    pub fn count_where(&self, f: impl Fn(&T) -> bool) -> usize {
+        let mut count = 0;
+        for (_, item) in self.iter_with_handles() {
+            if f(item) {
+                count += 1;
+            }
+        }
+        return count;
+    }
+    /// Get the live element with the largest key, or None if empty.
+    /// On ties the highest index wins, like Iterator::max_by_key.
+    pub fn max_by_key<K: Ord>(&self, f: impl Fn(&T) -> K) -> Option<(EntryHandle<T>, &T)> {
+        self.iter_with_handles().max_by_key(|(_, item)| f(item))
+    }
+    /// Get the live element with the smallest key, or None if empty.
+    /// On ties the lowest index wins, like Iterator::min_by_key.
+    pub fn min_by_key<K: Ord>(&self, f: impl Fn(&T) -> K) -> Option<(EntryHandle<T>, &T)> {
+        self.iter_with_handles().min_by_key(|(_, item)| f(item))
+    }
+    
+    /// Move all live elements to the lowest indices, calling
+    /// on_move(old, new) for every element that moved, so that side
+    /// tables keyed by handle can be patched in the same pass.
+    /// Moved elements get a fresh generation at their new index, so
+    /// old handles to them stop resolving. The backing vec keeps its
+    /// length; the vacated tail goes on the freelist, lowest index first.
+    /// Reserved and retired slots (see reserve_handle and
+    /// set_generation_limit) stay where they are.
+    pub fn compact_with<F: FnMut(EntryHandle<T>, EntryHandle<T>)>(&mut self, mut on_move: F) {
+        let mut dst = 0;
+        for src in 0..self.generations.len() {
+            if self.is_live(src) && let Some((old, new)) = self.compact_step(src, &mut dst) {
+                on_move(old, new);
+            }
+        }
+        self.finish_compaction(dst);
+    }
+    /// Like compact_with, but collects the (old, new) handle pairs.
+    pub fn compact(&mut self) -> Vec<(EntryHandle<T>, EntryHandle<T>)> {
+        let mut remap = Vec::new();
+        self.compact_with(|old, new| remap.push((old, new)));
+        return remap;
+    }
+    /// compact, then drop the free slots at the end and shrink the
+    /// backing vecs to fit, to give memory back from an idle arena.
+    /// Slots pushed later start above the dropped slots' generations, so
+    /// old handles to them stay stale.
+    pub fn compact_and_shrink(&mut self) -> Vec<(EntryHandle<T>, EntryHandle<T>)> {
+        let remap = self.compact();
+        // Reserved and retired slots aren't on the freelist, so they're kept
+        let mut on_freelist = vec![false; self.generations.len()];
+        for &index in self.freelist.iter() {
+            on_freelist[index] = true;
+        }
+        let new_len = on_freelist.iter().rposition(|&free| !free).map_or(0, |index| index + 1);
+        self.truncate_free(new_len);
+        self.generations.shrink_to_fit();
+        self.data.shrink_to_fit();
+        self.seqs.shrink_to_fit();
+        self.disabled.shrink_to_fit();
+        self.freelist.shrink_to_fit();
+        // Give the pinned capacity back, so allocs up to it don't move
+        // the new buffers
+        if let Some(pinned) = self.pinned {
+            self.pin_capacity(pinned);
+        }
+        return remap;
+    }
+    /// Rewrite every generation to the smallest one with the same
+    /// parity (live 0, free 1), e.g. before serializing. Returns (old,
+    /// new) pairs for live elements and pending reservations whose
+    /// handle changed. Every other handle must be dropped: it may now
+    /// resolve to an unrelated element. Slots retired by the generation
+    /// limit return to the freelist, since there's no old handle left
+    /// to protect; an append-only arena keeps them retired.
+    pub fn normalize_generations(&mut self) -> Vec<(EntryHandle<T>, EntryHandle<T>)> {
+        let mut remap = Vec::new();
+        for index in 0..self.generations.len() {
+            if !self.append_only && self.is_retired(index) {
+                self.freelist.push(index);
+            }
+            let generation = self.generations[index];
+            self.generations[index] = generation & 1;
+            // A reservation's handle is one above its free generation
+            let reserved = self.reserved.contains(&index);
+            let issued = if reserved { generation + 1 } else { generation };
+            let renewed = if reserved { 2 } else { generation & 1 };
+            if (issued & 1) == 0 && issued != renewed {
+                remap.push((
+                    EntryHandle { generation: issued, index, enforce_typing: PhantomData },
+                    EntryHandle { generation: renewed, index, enforce_typing: PhantomData },
+                ));
+            }
+        }
+        if !self.append_only {
+            self.retired = 0;
+        }
+        self.fresh_generation = 0;
+        return remap;
+    }
+    /// Set the number of slots, like Vec::resize for preallocated pools.
+    /// Growing appends free slots to the freelist, reused lowest index
+    /// first. Shrinking drops trailing free slots, and fails, changing
+    /// nothing, if one of them is live or reserved.
+    pub fn resize(&mut self, new_len: usize) -> Result<(), ResizeError> {
+        for index in new_len..self.generations.len() {
+            if self.is_live(index) || self.reserved.contains(&index) {
+                return Err(ResizeError { generation: self.generations[index], index });
+            }
+        }
+        self.grow_free(new_len);
+        self.truncate_free(new_len);
+        return Ok(());
+    }
+    /// Number of slots retired by the generation limit or because the
+    /// arena is append-only, see set_generation_limit.
+    pub fn retired_count(&self) -> usize {
+        return self.retired;
+    }
+    /// Put slots retired by GenerationWrap::Retire back on the freelist.
+    /// To keep old handles stale, every slot's generation is moved up
+    /// to the next multiple of the limit above all current generations,
+    /// so live elements and pending reservations get new handles:
+    /// on_move(old, new) is called for each. Returns how many slots were
+    /// reclaimed. Does nothing in an append-only arena.
+    pub fn reclaim_retired<F: FnMut(EntryHandle<T>, EntryHandle<T>)>(&mut self, mut on_move: F) -> usize {
+        let Some((limit, GenerationWrap::Retire)) = self.generation_limit else {
+            return 0;
+        };
+        if self.append_only || self.retired == 0 {
+            return 0;
+        }
+        let highest = self.generations.iter().copied().max().unwrap_or(0).max(self.fresh_generation);
+        let base = (highest / limit + 1) * limit;
+        let reclaimed = self.retired;
+        for index in 0..self.generations.len() {
+            let old = self.generations[index];
+            if self.is_retired(index) {
+                self.freelist.push(index);
+            }
+            self.generations[index] = base + (old & 1);
+            if self.is_live(index) {
+                on_move(
+                    EntryHandle { generation: old, index, enforce_typing: PhantomData },
+                    EntryHandle { generation: base, index, enforce_typing: PhantomData },
+                );
+            } else if self.reserved.contains(&index) {
+                // A reservation's handle is one above its free generation
+                on_move(
+                    EntryHandle { generation: old + 1, index, enforce_typing: PhantomData },
+                    EntryHandle { generation: base + 2, index, enforce_typing: PhantomData },
+                );
+            }
+        }
+        self.retired = 0;
+        self.fresh_generation = base;
+        return reclaimed;
+    }
+    /// Fraction of slots up to the highest live index that are not
+    /// live, from 0.0 (no holes, or empty) towards 1.0. compact brings it
+    /// to 0.0, except for reserved and retired slots it can't move past.
+    pub fn fragmentation(&self) -> f64 {
+        let Some(high) = self.live_high_index() else {
+            return 0.0;
+        };
+        let span = high + 1;
+        return (span - self.len()) as f64 / span as f64;
+    }
+    /// Get an iterator yields (start, len) for each maximal run of
+    /// slots that aren't live, in index order, e.g. to draw a memory
+    /// map. Reserved and retired slots count as part of a run.
+    pub fn hole_runs(&self) -> impl Iterator<Item=(usize, usize)> + '_ {
+        let mut index = 0;
+        std::iter::from_fn(move || {
+            while index < self.generations.len() && self.is_live(index) {
+                index += 1;
+            }
+            let start = index;
+            while index < self.generations.len() && !self.is_live(index) {
+                index += 1;
+            }
+            return (index > start).then_some((start, index - start));
+        })
+    }
+    /// Length of the longest run of consecutive existing slots that
+    /// alloc could reuse, or 0 if there are none. Like hole_runs, but
+    /// reserved and retired slots end a run.
+    pub fn max_contiguous_free(&self) -> usize {
+        let mut longest = 0;
+        let mut run = 0;
+        for index in 0..self.generations.len() {
+            if self.is_live(index) || self.reserved.contains(&index) || self.is_retired(index) {
+                run = 0;
+            } else {
+                run += 1;
+                longest = longest.max(run);
+            }
+        }
+        return longest;
+    }
+    /// compact, but only if fragmentation() >= threshold. Cheap when it
+    /// does nothing beyond the scan for the highest live index.
+    pub fn maybe_compact(&mut self, threshold: f64) -> Option<Vec<(EntryHandle<T>, EntryHandle<T>)>> {
+        if self.fragmentation() < threshold {
+            return None;
+        }
+        return Some(self.compact());
+    }
+    
+    /// Walk live elements in index order while compacting, e.g. to
+    /// spread compaction over a pass that runs anyway. Each element is
+    /// moved into the lowest hole before it is yielded, so it comes
+    /// with its new handle and old handles to it stop resolving as the
+    /// walk reaches them. finish() returns the (old, new) pairs like
+    /// compact(); stopping early or dropping the walker compacts the
+    /// rest. O(1) per step, O(slots) in all.
+    pub fn iter_compacting_mut(&mut self) -> CompactingIterMut<'_, T, A> {
+        CompactingIterMut { genvec: self, src: 0, dst: 0, remap: Vec::new(), done: false }
+    }
+    /// Get a cursor that walks live elements and can free the one it
+    /// last yielded, without collecting handles first.
+    pub fn cursor_mut(&mut self) -> CursorMut<'_, T, A> {
+        CursorMut { genvec: self, next: 0, current: None }
+    }
+    
+    /// Consume the arena into a map from each live handle to its value,
+    /// for code that wants a map. The on_free callback runs for each
+    /// element, as with the drains.
+    pub fn into_handle_map(mut self) -> HashMap<EntryHandle<T>, T> {
+        let mut map = HashMap::with_capacity(self.len());
+        for index in 0..self.generations.len() {
+            if let Some(h) = self.handle_at(index) {
+                map.insert(h, self.remove_at(index));
+            }
+        }
+        return map;
+    }
+    /// Move all live elements into dst, leaving self empty.
+    /// Returns (old, new) handle pairs, old being the handle in self.
+    /// The vacated slots are freed as usual, so old handles stay stale.
+    pub fn drain_into<B: Allocator>(&mut self, dst: &mut GenVec<T, B>) -> Vec<(EntryHandle<T>, EntryHandle<T>)> {
+        let mut remap = Vec::new();
+        for index in 0..self.generations.len() {
+            let Some(old) = self.handle_at(index) else {
+                continue;
+            };
+            let data = self.remove_at(index);
+            remap.push((old, dst.alloc(data)));
+        }
+        return remap;
+    }
+    /// Move one element into dst, freeing its slot here. Returns its
+    /// handle in dst, or None (moving nothing) if h is invalid.
+    pub fn migrate<B: Allocator>(&mut self, h: EntryHandle<T>, dst: &mut GenVec<T, B>) -> Option<EntryHandle<T>> {
+        if !self.exists(h) {
+            return None;
+        }
+        let data = self.remove_at(h.index);
+        return Some(dst.alloc(data));
+    }
+    /// Free every live element allocated before sequence number seq
+    /// (see iter_with_seq and next_seq) and return the values in index
+    /// order, e.g. to expire old entries. Newer elements are untouched.
+    pub fn drain_older_than(&mut self, seq: u64) -> Vec<T> {
+        let mut values = Vec::new();
+        for index in 0..self.generations.len() {
+            if self.is_live(index) && self.seqs[index] < seq {
+                values.push(self.remove_at(index));
+            }
+        }
+        return values;
+    }
+    /// Free every live element for which f returns true and return
+    /// their values in index order. Unlike retain, the values are kept.
+    pub fn take_where(&mut self, f: impl Fn(&T) -> bool) -> Vec<T> {
+        let mut values = Vec::new();
+        for index in 0..self.generations.len() {
+            if self.slot(index).is_some_and(&f) {
+                values.push(self.remove_at(index));
+            }
+        }
+        return values;
+    }
+    /// Free every live element, yielding the values in ascending key
+    /// order (equal keys in index order). The arena is empty as soon as
+    /// this returns, and keeps its capacity.
+    pub fn drain_sorted_by_key<K: Ord>(&mut self, f: impl Fn(&T) -> K) -> impl Iterator<Item=T> {
+        let mut values = Vec::with_capacity(self.len());
+        for index in 0..self.generations.len() {
+            if self.is_live(index) {
+                values.push(self.remove_at(index));
+            }
+        }
+        values.sort_by_key(|item| f(item));
+        return values.into_iter();
+    }
+    
+    /// Read-only join_mut: yields (index, &T, &U) for indices live in
+    /// both arenas. Generations are not compared.
+    pub fn zip_live<'a, U, B: Allocator>(&'a self, other: &'a GenVec<U, B>) -> impl Iterator<Item=(usize, &'a T, &'a U)> + 'a {
+        self.generations.iter()
+            .zip(self.data.iter())
+            .zip(other.generations.iter().zip(other.data.iter()))
+            .enumerate()
+            .filter_map(
+                |(index, ((&a_generation, a), (&b_generation, b)))| Some((
+                    index,
+                    filled(a_generation, a)?,
+                    filled(b_generation, b)?
+                ))
+            )
+    }
+    /// Join two arenas by index, for layouts where entity i lives at
+    /// index i in several arenas. Yields indices live in both.
+    /// Generations are not compared, since each arena has its own.
+    pub fn join_mut<'a, U, B: Allocator>(&'a mut self, other: &'a mut GenVec<U, B>) -> impl Iterator<Item=(usize, &'a mut T, &'a mut U)> + 'a {
+        self.generations.iter()
+            .zip(self.data.iter_mut())
+            .zip(other.generations.iter().zip(other.data.iter_mut()))
+            .enumerate()
             .filter_map(
-                |item| ((item.generation & 1) == 0).then_some(&mut item.data)
+                |(index, ((&a_generation, a), (&b_generation, b)))| Some((
+                    index,
+                    filled_mut(a_generation, a)?,
+                    filled_mut(b_generation, b)?
+                ))
             )
     }
+    
+    /// Map each generation value to the number of slots at it, over
+    /// all slots, live or free. A skewed histogram means a few slots
+    /// are being reused heavily.
+    pub fn generation_histogram(&self) -> BTreeMap<u64, usize> {
+        let mut histogram = BTreeMap::new();
+        for &generation in &self.generations {
+            *histogram.entry(generation).or_insert(0) += 1;
+        }
+        return histogram;
+    }
+    
+    /// Handle to the slot at index, if it is live.
+    fn handle_at(&self, index: usize) -> Option<EntryHandle<T>> {
+        let generation = *self.generations.get(index)?;
+        if (generation & 1) != 0 {
+            return None;
+        }
+        return Some(EntryHandle { generation, index, enforce_typing: PhantomData });
+    }
+    /// Mark-and-sweep: free every element not reachable from roots,
+    /// following the handles neighbors returns for each element. Stale
+    /// handles are ignored. Returns how many elements were freed.
+    pub fn gc<F: Fn(&T) -> Vec<EntryHandle<T>>>(&mut self, roots: &[EntryHandle<T>], neighbors: F) -> usize {
+        let mut marked = vec![false; self.generations.len()];
+        let mut stack: Vec<EntryHandle<T>> = roots.to_vec();
+        while let Some(h) = stack.pop() {
+            let Some(item) = self.get_ref(h) else {
+                continue;
+            };
+            if marked[h.index] {
+                continue;
+            }
+            marked[h.index] = true;
+            stack.extend(neighbors(item));
+        }
+        let mut freed = 0;
+        for (index, reachable) in marked.into_iter().enumerate() {
+            if self.is_live(index) && !reachable {
+                let data = self.remove_at(index);
+                freed += 1;
+            }
+        }
+        return freed;
+    }
+    /// Free every live element for which f returns false.
+    pub fn retain<F: FnMut(&T) -> bool>(&mut self, mut f: F) {
+        for index in 0..self.generations.len() {
+            if let Some(h) = self.handle_at(index) && !f(self.index_ref(h)) {
+                self.free(h);
+            }
+        }
+    }
+    /// Free every live element whose entry in a side table keyed by
+    /// handle matches pred, e.g. to despawn everything flagged dead.
+    /// Entries for stale handles are ignored. Returns how many were
+    /// freed.
+    pub fn free_where_flagged<V, S: BuildHasher>(&mut self, flags: &HashMap<EntryHandle<T>, V, S>, pred: impl Fn(&V) -> bool) -> usize {
+        let mut count = 0;
+        for index in 0..self.generations.len() {
+            if let Some(h) = self.handle_at(index) && flags.get(&h).is_some_and(&pred) {
+                self.free(h);
+                count += 1;
+            }
+        }
+        return count;
+    }
+    /// Call f with each live (handle, &mut item) in index order and
+    /// free the element if f returns false, in one pass, e.g. to update
+    /// entities and despawn the dead ones.
+    pub fn process_retain(&mut self, mut f: impl FnMut(EntryHandle<T>, &mut T) -> bool) {
+        for index in 0..self.generations.len() {
+            let Some(h) = self.handle_at(index) else {
+                continue;
+            };
+            if !f(h, self.slot_mut(index).unwrap()) {
+                self.free(h);
+            }
+        }
+    }
+    /// Like retain, but returns the handles of the freed elements
+    /// (as they were before the free), e.g. to propagate despawns.
+    pub fn retain_report<F: FnMut(&T) -> bool>(&mut self, mut f: F) -> Vec<EntryHandle<T>> {
+        let mut freed = Vec::new();
+        for index in 0..self.generations.len() {
+            if let Some(h) = self.handle_at(index) && !f(self.index_ref(h)) {
+                self.free(h);
+                freed.push(h);
+            }
+        }
+        return freed;
+    }
+    /// Free every live element whose key was already seen at a lower
+    /// index, so one element per key survives. Returns the number freed.
+    pub fn dedup_by_key<K: Hash + Eq>(&mut self, f: impl Fn(&T) -> K) -> usize {
+        let mut seen = HashSet::new();
+        return self.retain_report(|item| seen.insert(f(item))).len();
+    }
+    
+    /// Free every element, leaving the freelist sorted so the next
+    /// allocs land at index 0, 1, 2... for locality and deterministic
+    /// handles. Keeps capacity, and generations, so old handles stay stale.
+    /// Pending reservations are cancelled.
+    pub fn reset_dense(&mut self) {
+        self.freelist.clear();
+        for index in (0..self.generations.len()).rev() {
+            if self.is_retired(index) {
+                continue;
+            }
+            if self.reserved.contains(&index) {
+                self.cancel_reserved(index);
+            }
+            let data = self.is_live(index).then(|| self.take_slot(index));
+            self.release(index);
+        }
+    }
+    /// reset_dense, then alloc each item, e.g. to rebuild per frame.
+    /// Items reuse the existing slots from index 0 up, so nothing
+    /// reallocates unless there are more items than slots. Every old
+    /// handle stops resolving; the items land at index 0, 1, 2...
+    pub fn refill(&mut self, items: impl IntoIterator<Item=T>) {
+        self.reset_dense();
+        for item in items {
+            self.alloc(item);
+        }
+    }
+    
+    /// Walk live elements mutably, where each step also gets a read-only
+    /// view of every other element, e.g. for simulations where updating
+    /// one element reads its neighbours.
+    pub fn iter_mut_with_rest(&mut self) -> IterMutWithRest<'_, T> {
+        IterMutWithRest {
+            generations: &self.generations,
+            data: self.data.as_mut_ptr(),
+            next: 0,
+            enforce_typing: PhantomData
+        }
+    }
+    /// Walk every unordered pair of distinct live elements once, both
+    /// mutably, e.g. for pairwise interactions. O(n^2) over live elements.
+    /// Pairs come in index order: (a, b) before (a, c) before (b, c).
+    pub fn pairs_mut(&mut self) -> PairsMut<'_, T> {
+        let live = (0..self.generations.len()).filter(|&index| self.is_live(index)).collect();
+        PairsMut {
+            live,
+            data: self.data.as_mut_ptr(),
+            first: 0,
+            second: 0,
+            enforce_typing: PhantomData
+        }
+    }
+    
+    /// Split into n views over disjoint, contiguous index ranges that
+    /// together cover every slot, so threads can mutate their own range
+    /// without locks. Some views are empty if there are fewer slots than n.
+    pub fn split_ranges_mut(&mut self, n: usize) -> Vec<RangeViewMut<'_, T>> {
+        let len = self.generations.len();
+        let mut views = Vec::with_capacity(n);
+        let mut generations = &self.generations[..];
+        let mut data = &mut self.data[..];
+        let mut start = 0;
+        for i in 1..=n {
+            let end = i * len / n;
+            let (view_generations, rest_generations) = generations.split_at(end - start);
+            let (view_data, rest_data) = data.split_at_mut(end - start);
+            views.push(RangeViewMut { start, generations: view_generations, data: view_data });
+            generations = rest_generations;
+            data = rest_data;
+            start = end;
+        }
+        return views;
+    }
+    /// Append n new live slots whose values f builds in parallel from
+    /// their position 0..n in the batch, on scoped std threads (one per
+    /// available core). Handles come back in position order. Each
+    /// thread writes its own chunk of the reserved slots, and the slots
+    /// only become live once all threads are done, so a panic in f
+    /// leaves the arena as it was (leaking values already built).
+    pub fn par_alloc_with(&mut self, n: usize, f: impl Fn(usize) -> T + Sync) -> Vec<EntryHandle<T>> where T: Send {
+        let start = self.generations.len();
+        self.check_pinned(start + n);
+        self.reserve(n);
+        let threads = std::thread::available_parallelism().map_or(1, |threads| threads.get());
+        let chunk = n.div_ceil(threads).max(1);
+        let spare = &mut self.data.spare_capacity_mut()[..n];
+        let f = &f;
+        std::thread::scope(|scope| {
+            for (c, slots) in spare.chunks_mut(chunk).enumerate() {
+                scope.spawn(move || {
+                    for (offset, slot) in slots.iter_mut().enumerate() {
+                        slot.write(MaybeUninit::new(f(c * chunk + offset)));
+                    }
+                });
+            }
+        });
+        // SAFETY: the threads wrote every slot in start..start + n
+        unsafe { self.data.set_len(start + n) };
+        if self.dense_prefix == start {
+            self.dense_prefix += n;
+        }
+        let mut handles = Vec::with_capacity(n);
+        for index in start..start + n {
+            self.generations.push(self.fresh_generation);
+            let seq = self.bump_seq();
+            self.seqs.push(seq);
+            self.disabled.push(false);
+            self.live += 1;
+            self.notify_alloc(index);
+            handles.push(EntryHandle { generation: self.fresh_generation, index, enforce_typing: PhantomData });
+        }
+        return handles;
+    }
+    
+    /// Give a live value a brand-new handle, so every outstanding handle
+    /// to it stops resolving. Returns None if h is stale.
+    /// The value may end up at the same index, with a newer generation.
+    pub fn rehandle(&mut self, h: EntryHandle<T>) -> Option<EntryHandle<T>> {
+        if !self.exists(h) {
+            return None;
+        }
+        let data = self.remove_at(h.index);
+        return Some(self.alloc(data));
+    }
 }
 
-impl <T: Copy> GenVec<T> {
+impl<T: Copy, A: Allocator> GenVec<T, A> {
     /// Get a copy of T or panic.
     pub fn index_copy(&self, h: EntryHandle<T>) -> T {
-        let el = &self.vec[h.index];
-        if el.generation != h.generation {
-            panic!("Invalid handle: {:?}", (h.generation, h.index));
-        }
-        return el.data;
+        return *self.index_ref(h);
     }
     /// Get a Some(copy of T) or None.
     pub fn get_copy(&self, h: EntryHandle<T>) -> Option<T> {
-        if self.vec[h.index].generation != h.generation {
-            return None;
-        }
-        return Some(self.vec[h.index].data);
+        return self.get_ref(h).copied();
+    }
+    /// Get an iterator yields copies of items.
+    pub fn copied(&self) -> impl Iterator<Item=T> + '_ {
+        self.iter_with_handles().map(|(_, &item)| item)
+    }
+    /// Push get_copy(h) for each handle onto out, in order.
+    pub fn get_copy_batch(&self, handles: &[EntryHandle<T>], out: &mut Vec<Option<T>>) {
+        out.reserve(handles.len());
+        out.extend(handles.iter().map(|&h| self.get_copy(h)));
     }
 }
 
-impl<'a, T> IntoIterator for &'a GenVec<T> {
-    type Item = &'a T;
+impl<T: Default, A: Allocator> GenVec<T, A> {
+    /// Make every free slot live with a default value, emptying the
+    /// freelist. Returns handles to the newly live slots.
+    pub fn fill_all_default(&mut self) -> Vec<EntryHandle<T>> {
+        let count = self.freelist.len();
+        return (0..count).map(|_| self.alloc(T::default())).collect();
+    }
+}
+
+impl<T: Clone + PartialEq, A: Allocator> GenVec<T, A> {
+    /// The patch that makes self's live set match other's, e.g. to
+    /// send a delta to a replica holding a copy of self. Slots are
+    /// matched by index and generation.
+    pub fn diff_patch<B: Allocator>(&self, other: &GenVec<T, B>) -> Patch<T> {
+        let (added, removed) = other.changed_handles(self);
+        let added = added.into_iter().map(|h| (h, other.index_ref(h).clone())).collect();
+        let mut changed = Vec::new();
+        for (h, item) in self.iter_with_handles() {
+            if let Some(target) = other.get_ref(h) && target != item {
+                changed.push((h, target.clone()));
+            }
+        }
+        return Patch { removed, added, changed };
+    }
+    /// Apply a patch from diff_patch: afterwards self has the same live
+    /// handles and values as the patch's target. Free slots are only
+    /// synced as far as that needs; their generations, the freelist
+    /// order and reservations may still differ. A live slot in the way
+    /// of an added handle is freed.
+    pub fn apply_patch(&mut self, patch: Patch<T>) {
+        for h in patch.removed {
+            self.free(h);
+        }
+        for (h, data) in patch.changed {
+            if let Some(item) = self.get_mut(h) {
+                *item = data;
+            }
+        }
+        let mut claimed = HashSet::new();
+        for (h, data) in patch.added {
+            if (h.generation & 1) != 0 {
+                panic!("Invalid handle: {:?}", (h.generation, h.index));
+            }
+            self.grow_free(h.index + 1);
+            if self.is_live(h.index) {
+                let replaced = self.remove_at(h.index);
+            }
+            if !self.reserved.remove(&h.index) {
+                if self.is_retired(h.index) {
+                    self.retired -= 1;
+                } else {
+                    claimed.insert(h.index);
+                }
+            }
+            self.generations[h.index] = h.generation;
+            self.data[h.index].write(data);
+            self.seqs[h.index] = self.bump_seq();
+            self.live += 1;
+            self.notify_alloc(h.index);
+        }
+        self.freelist.retain(|index| !claimed.contains(index));
+        self.recount_dense_prefix();
+    }
+}
+
+impl<T: Hash, A: Allocator> GenVec<T, A> {
+    /// Digest of the live set, e.g. to detect replicas drifting apart
+    /// in lockstep. Hashes each live slot's index, generation and value
+    /// in index order. Free slots aren't included, so arenas that hold
+    /// the same handles and values match even if their freelists or
+    /// free generations differ. DefaultHasher's algorithm isn't
+    /// specified and T::hash may write usize lengths, so digests are
+    /// only comparable between arenas in one build for one target.
+    pub fn checksum(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        for (h, item) in self.iter_with_handles() {
+            (h.index as u64).hash(&mut hasher);
+            h.generation.hash(&mut hasher);
+            item.hash(&mut hasher);
+        }
+        return hasher.finish();
+    }
+}
+
+impl<T: Clone, A: Allocator> GenVec<T, A> {
+    /// Clones of the live values in index order, in a Vec of exactly
+    /// len() capacity.
+    pub fn cloned_values(&self) -> Vec<T> {
+        let mut values = Vec::with_capacity(self.len());
+        values.extend(self.iter_with_handles().map(|(_, item)| item.clone()));
+        return values;
+    }
+    /// Clone the live values into a Vec without holes, e.g. for a GPU
+    /// upload, with a map from each handle to its value's position.
+    /// Values are in index order.
+    pub fn to_packed(&self) -> (Vec<T>, HashMap<EntryHandle<T>, usize>) {
+        let mut values = Vec::with_capacity(self.len());
+        let mut positions = HashMap::with_capacity(self.len());
+        for (h, item) in self.iter_with_handles() {
+            positions.insert(h, values.len());
+            values.push(item.clone());
+        }
+        return (values, positions);
+    }
+}
+
+impl<T: Clone, A: Allocator + Clone> GenVec<T, A> {
+    /// Capture the full state, e.g. for undo. Handles valid now are
+    /// valid again after restore(snapshot).
+    pub fn snapshot(&self) -> Snapshot<T, A> {
+        Snapshot { genvec: self.clone() }
+    }
+    /// Replace the current state with a snapshot's. Callbacks set on
+    /// self are kept.
+    pub fn restore(&mut self, snapshot: Snapshot<T, A>) {
+        let mut old = snapshot.genvec;
+        std::mem::swap(&mut self.generations, &mut old.generations);
+        std::mem::swap(&mut self.data, &mut old.data);
+        std::mem::swap(&mut self.seqs, &mut old.seqs);
+        std::mem::swap(&mut self.live, &mut old.live);
+        std::mem::swap(&mut self.freelist, &mut old.freelist);
+        std::mem::swap(&mut self.reserved, &mut old.reserved);
+        std::mem::swap(&mut self.disabled, &mut old.disabled);
+        std::mem::swap(&mut self.retired, &mut old.retired);
+        std::mem::swap(&mut self.dense_prefix, &mut old.dense_prefix);
+        std::mem::swap(&mut self.fresh_generation, &mut old.fresh_generation);
+        // old now holds the replaced state and drops it. The snapshot's
+        // vecs are only as big as its slots, so reserve the pin again
+        if let Some(pinned) = self.pinned {
+            self.pin_capacity(pinned);
+        }
+    }
+    /// Swap the whole contents of two arenas, e.g. the front and back
+    /// buffers of a simulation step. Handles don't name an arena, so
+    /// they follow their data: a handle from self resolves in other
+    /// afterwards, and the reverse. Settings and callbacks move along
+    /// with the contents.
+    pub fn swap_with(&mut self, other: &mut GenVec<T, A>) {
+        std::mem::swap(self, other);
+    }
+    /// Clone just the live elements into a packed arena: no holes,
+    /// fresh generations, empty freelist. Returns it with a map from
+    /// handles into self to handles into the copy. Unlike clone(), old
+    /// handles don't work in the copy. Reservations aren't copied;
+    /// the generation limit, zero-on-free and append-only settings are.
+    pub fn clone_compact(&self) -> (GenVec<T, A>, HashMap<EntryHandle<T>, EntryHandle<T>>) {
+        let mut copy = GenVec::with_capacity_in(self.len(), self.data.allocator().clone());
+        copy.generation_limit = self.generation_limit;
+        copy.zero_on_free = self.zero_on_free;
+        copy.append_only = self.append_only;
+        let mut remap = HashMap::with_capacity(self.len());
+        for (h, item) in self.iter_with_handles() {
+            let new = copy.alloc(item.clone());
+            if self.disabled[h.index] {
+                copy.disable(new);
+            }
+            remap.insert(h, new);
+        }
+        return (copy, remap);
+    }
+    /// Run a recorded sequence of operations, e.g. to replay a fuzz
+    /// case, and log what each did. Ops refer to handles by position
+    /// in this run's handle table, so a replay doesn't depend on the
+    /// handles the arena happens to hand out. Positions that don't
+    /// exist yet act like stale handles.
+    pub fn apply_ops(&mut self, ops: &[Op<T>]) -> Vec<OpResult<T>> {
+        let mut handles = Vec::new();
+        let mut log = Vec::with_capacity(ops.len());
+        for op in ops {
+            log.push(match op {
+                Op::Alloc(data) => {
+                    let h = self.alloc(data.clone());
+                    handles.push(h);
+                    OpResult::Allocated(h)
+                }
+                Op::Free(position) => match handles.get(*position) {
+                    Some(&h) => OpResult::Freed(self.strict_free(h).is_ok()),
+                    None => OpResult::Freed(false),
+                },
+                Op::Get(position) => {
+                    OpResult::Got(handles.get(*position).and_then(|&h| self.get_ref(h)).cloned())
+                }
+            });
+        }
+        return log;
+    }
+}
+
+impl<'a, T, A: Allocator> IntoIterator for &'a GenVec<T, A> {
+    type Item = &'a T;
     type IntoIter = impl Iterator<Item=&'a T> + 'a;
     fn into_iter(self) -> Self::IntoIter {
         self.iter()
     }
 }
 
-impl<'a, T> IntoIterator for &'a mut GenVec<T> {
+impl<'a, T, A: Allocator> IntoIterator for &'a mut GenVec<T, A> {
     type Item = &'a mut T;
     type IntoIter = impl Iterator<Item=&'a mut T> + 'a;
     fn into_iter(self) -> Self::IntoIter {
@@ -181,4 +2582,2877 @@ impl<T> Default for GenVec<T> {
     }
 }
 
+/// Clones keep the exact slot layout, so handles valid in the original
+/// are valid in the clone. Callbacks are not cloned.
+impl<T: Clone, A: Allocator + Clone> Clone for GenVec<T, A> {
+    fn clone(&self) -> Self {
+        self.map(T::clone)
+    }
+}
+
+impl<T, A: Allocator> Drop for GenVec<T, A> {
+    fn drop(&mut self) {
+        for (&generation, item) in self.generations.iter().zip(self.data.iter_mut()) {
+            if (generation & 1) == 0 {
+                // SAFETY: data is initialized while the generation is even
+                unsafe { item.assume_init_drop() };
+            }
+        }
+    }
+}
+
+/// Prints only live elements, as a map of index to value, after a
+/// summary like "GenVec (3 live / 10 slots / 7 free)".
+impl<T: fmt::Debug, A: Allocator> fmt::Debug for GenVec<T, A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "GenVec ({} live / {} slots / {} free) ",
+            self.len(), self.generations.len(), self.freelist.len())?;
+        f.debug_map()
+            .entries(self.iter_with_handles().map(|(h, item)| (h.index, item)))
+            .finish()
+    }
+}
+
+/// Borrow the elements at index in two arenas that share an index
+/// layout, if it is live in both. Like GenVec::join_mut, generations
+/// aren't compared, since each arena has its own.
+pub fn join_index_mut<'a, T, U, A: Allocator, B: Allocator>(a: &'a mut GenVec<T, A>, b: &'a mut GenVec<U, B>, index: usize) -> Option<(&'a mut T, &'a mut U)> {
+    return Some((a.slot_mut(index)?, b.slot_mut(index)?));
+}
+
+/// Get the elements at index in two arenas kept in lockstep, e.g. the
+/// same handle stored into differently typed arenas, if the slot is
+/// live at generation in both.
+pub fn get_parallel<'a, T, U, A: Allocator, B: Allocator>(a: &'a GenVec<T, A>, b: &'a GenVec<U, B>, index: usize, generation: u64) -> Option<(&'a T, &'a U)> {
+    return Some((a.slot_at(index, generation)?, b.slot_at(index, generation)?));
+}
+
+/// One step for GenVec::apply_ops. Free and Get take a position in the
+/// run's handle table: the handle from the nth Alloc is position n.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Op<T> {
+    Alloc(T),
+    Free(usize),
+    Get(usize),
+}
+
+/// What an Op did, see GenVec::apply_ops.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OpResult<T> {
+    Allocated(EntryHandle<T>),
+    /// Whether the handle was live.
+    Freed(bool),
+    Got(Option<T>),
+}
+
+/// Changes that turn one arena's live set into another's, see
+/// GenVec::diff_patch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Patch<T> {
+    /// Live in the source but not in the target.
+    pub removed: Vec<EntryHandle<T>>,
+    /// Live in the target but not in the source, with the value.
+    pub added: Vec<(EntryHandle<T>, T)>,
+    /// Live in both with different values, with the target's value.
+    pub changed: Vec<(EntryHandle<T>, T)>,
+}
+
+/// Saved state of a GenVec, see GenVec::snapshot.
+pub struct Snapshot<T, A: Allocator = Global> {
+    genvec: GenVec<T, A>,
+}
+
+/// The live handles of a GenVec as an IntoIterator, see
+/// GenVec::handles_view.
+pub struct HandlesView<'a, T, A: Allocator = Global> {
+    genvec: &'a GenVec<T, A>,
+}
+
+impl<'a, T, A: Allocator> IntoIterator for HandlesView<'a, T, A> {
+    type Item = EntryHandle<T>;
+    type IntoIter = impl Iterator<Item=EntryHandle<T>> + 'a;
+    fn into_iter(self) -> Self::IntoIter {
+        self.genvec.iter_with_handles().map(|(h, _)| h)
+    }
+}
+
+/// Walks live elements in index order, see GenVec::cursor_mut.
+pub struct CursorMut<'a, T, A: Allocator = Global> {
+    genvec: &'a mut GenVec<T, A>,
+    next: usize,
+    current: Option<usize>,
+}
+
+impl<'a, T, A: Allocator> CursorMut<'a, T, A> {
+    /// Advance to the next live element and get a &mut to it.
+    #[allow(clippy::should_implement_trait)] // lending, so it can't be Iterator::next
+    pub fn next(&mut self) -> Option<&mut T> {
+        while self.next < self.genvec.generations.len() {
+            let index = self.next;
+            self.next += 1;
+            if self.genvec.is_live(index) {
+                self.current = Some(index);
+                return self.genvec.slot_mut(index);
+            }
+        }
+        self.current = None;
+        return None;
+    }
+    /// Handle of the element last yielded by next(), if it is still live.
+    pub fn current_handle(&self) -> Option<EntryHandle<T>> {
+        return self.genvec.handle_at(self.current?);
+    }
+    /// Free the element last yielded by next(). The next call to
+    /// next() continues with the following element.
+    pub fn free_current(&mut self) {
+        if let Some(h) = self.current_handle() {
+            self.genvec.free(h);
+            self.current = None;
+        }
+    }
+}
+
+/// A &mut T together with the handle it was borrowed through, see
+/// GenVec::borrow_mut. Derefs to T.
+pub struct Guard<'a, T> {
+    value: &'a mut T,
+    generation: &'a u64,
+    handle: EntryHandle<T>,
+}
+
+impl<'a, T> Guard<'a, T> {
+    /// The handle the value was borrowed through, valid for as long as
+    /// the guard lives.
+    pub fn handle(&self) -> EntryHandle<T> {
+        self.handle
+    }
+}
+
+impl<'a, T> Deref for Guard<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        self.value
+    }
+}
+
+impl<'a, T> DerefMut for Guard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.value
+    }
+}
+
+impl<'a, T> Drop for Guard<'a, T> {
+    fn drop(&mut self) {
+        // The arena is borrowed for the guard's lifetime, so this holds
+        debug_assert_eq!(*self.generation, self.handle.generation);
+    }
+}
+
+/// Compacts while walking live elements, see
+/// GenVec::iter_compacting_mut. Not an Iterator, like CursorMut.
+pub struct CompactingIterMut<'a, T, A: Allocator = Global> {
+    genvec: &'a mut GenVec<T, A>,
+    src: usize,
+    dst: usize,
+    remap: Vec<(EntryHandle<T>, EntryHandle<T>)>,
+    done: bool,
+}
+
+impl<'a, T, A: Allocator> CompactingIterMut<'a, T, A> {
+    /// Move the next live element into place and get its (new) handle
+    /// and a &mut to it.
+    #[allow(clippy::should_implement_trait)] // lending, so it can't be Iterator::next
+    pub fn next(&mut self) -> Option<(EntryHandle<T>, &mut T)> {
+        while self.src < self.genvec.generations.len() {
+            let src = self.src;
+            self.src += 1;
+            if !self.genvec.is_live(src) {
+                continue;
+            }
+            let index = match self.genvec.compact_step(src, &mut self.dst) {
+                Some((old, new)) => {
+                    self.remap.push((old, new));
+                    new.index
+                }
+                None => src,
+            };
+            let h = self.genvec.handle_at(index).unwrap();
+            return Some((h, self.genvec.slot_mut(index).unwrap()));
+        }
+        return None;
+    }
+    /// Compact whatever wasn't walked yet and return every (old, new)
+    /// pair, as compact() would.
+    pub fn finish(mut self) -> Vec<(EntryHandle<T>, EntryHandle<T>)> {
+        self.complete();
+        return std::mem::take(&mut self.remap);
+    }
+    fn complete(&mut self) {
+        if self.done {
+            return;
+        }
+        while self.next().is_some() {}
+        self.genvec.finish_compaction(self.dst);
+        self.done = true;
+    }
+}
+
+impl<'a, T, A: Allocator> Drop for CompactingIterMut<'a, T, A> {
+    fn drop(&mut self) {
+        self.complete();
+    }
+}
+
+/// See GenVec::iter_mut_with_rest. Like CursorMut this is not an
+/// Iterator: each step borrows the walker, so only one (&mut T, RestView)
+/// pair is alive at a time.
+///
+/// Safety: both the &mut T and the RestView are derived from the same
+/// raw pointer to the data vec, and RestView never touches the index
+/// the &mut T points at. Generations are only read, so sharing them is
+/// fine. The walker holds the GenVec's &mut borrow for its lifetime,
+/// so nothing else can reach the data meanwhile.
+pub struct IterMutWithRest<'a, T> {
+    generations: &'a [u64],
+    data: *mut MaybeUninit<T>,
+    next: usize,
+    enforce_typing: PhantomData<&'a mut [MaybeUninit<T>]>
+}
+
+impl<'a, T> IterMutWithRest<'a, T> {
+    /// Advance to the next live element.
+    #[allow(clippy::should_implement_trait)] // lending, so it can't be Iterator::next
+    pub fn next(&mut self) -> Option<(&mut T, RestView<'_, T>)> {
+        while self.next < self.generations.len() {
+            let index = self.next;
+            self.next += 1;
+            // SAFETY: index is in bounds, and no other reference to it
+            // exists, see the struct docs
+            if let Some(item) = filled_mut(self.generations[index], unsafe { &mut *self.data.add(index) }) {
+                let rest = RestView {
+                    generations: self.generations,
+                    data: self.data,
+                    skip: index,
+                    enforce_typing: PhantomData
+                };
+                return Some((item, rest));
+            }
+        }
+        return None;
+    }
+}
+
+/// See GenVec::pairs_mut. Not an Iterator for the same reason as
+/// IterMutWithRest: an element is in many pairs, so only one pair may
+/// be borrowed at a time.
+///
+/// Safety: the two indices of a pair are always distinct live slots,
+/// so the two &mut T don't alias. The walker holds the GenVec's &mut
+/// borrow, so no slot is freed meanwhile.
+pub struct PairsMut<'a, T> {
+    live: Vec<usize>,
+    data: *mut MaybeUninit<T>,
+    first: usize, // positions in live of the last yielded pair
+    second: usize,
+    enforce_typing: PhantomData<&'a mut [MaybeUninit<T>]>
+}
+
+impl<'a, T> PairsMut<'a, T> {
+    /// Advance to the next pair.
+    #[allow(clippy::should_implement_trait)] // lending, so it can't be Iterator::next
+    pub fn next(&mut self) -> Option<(&mut T, &mut T)> {
+        self.second += 1;
+        if self.second >= self.live.len() {
+            self.first += 1;
+            self.second = self.first + 1;
+        }
+        if self.second >= self.live.len() {
+            return None;
+        }
+        let (a, b) = (self.live[self.first], self.live[self.second]);
+        // SAFETY: a != b and both are live, see the struct docs
+        return Some(unsafe { ((*self.data.add(a)).assume_init_mut(), (*self.data.add(b)).assume_init_mut()) });
+    }
+}
+
+/// Read-only view of every element but the one currently borrowed
+/// mutably, see GenVec::iter_mut_with_rest.
+pub struct RestView<'a, T> {
+    generations: &'a [u64],
+    data: *const MaybeUninit<T>,
+    skip: usize,
+    enforce_typing: PhantomData<&'a [MaybeUninit<T>]>
+}
+
+impl<'a, T> RestView<'a, T> {
+    /// Get a Some(&T) or None. Also None for the current element.
+    pub fn get_ref(&self, h: EntryHandle<T>) -> Option<&T> {
+        if h.index == self.skip || h.index >= self.generations.len() || self.generations[h.index] != h.generation {
+            return None;
+        }
+        // SAFETY: index is in bounds and not the mutably borrowed one
+        return filled(h.generation, unsafe { &*self.data.add(h.index) });
+    }
+    /// Get an iterator yields (handle, &item) for every other live element.
+    pub fn iter_with_handles(&self) -> impl Iterator<Item=(EntryHandle<T>, &T)> + '_ {
+        self.generations.iter()
+            .enumerate()
+            .filter(|&(index, _)| index != self.skip)
+            .filter_map(
+                |(index, &generation)| Some((
+                    EntryHandle { generation, index, enforce_typing: PhantomData },
+                    // SAFETY: index is in bounds and not the mutably borrowed one
+                    filled(generation, unsafe { &*self.data.add(index) })?
+                ))
+            )
+    }
+    /// Get an iterator yields &items for every other live element.
+    pub fn iter(&self) -> impl Iterator<Item=&T> + '_ {
+        self.iter_with_handles().map(|(_, item)| item)
+    }
+}
+
+/// Mutable view of the slots in one index range, see GenVec::split_ranges_mut.
+pub struct RangeViewMut<'a, T> {
+    start: usize,
+    generations: &'a [u64],
+    data: &'a mut [MaybeUninit<T>],
+}
+
+impl<'a, T> RangeViewMut<'a, T> {
+    /// The backing indices this view covers.
+    pub fn range(&self) -> Range<usize> {
+        self.start..self.start + self.generations.len()
+    }
+    /// Get a Some(&mut T) or None. Also None if the handle's index is
+    /// outside this view's range.
+    pub fn get_mut(&mut self, h: EntryHandle<T>) -> Option<&mut T> {
+        let offset = h.index.checked_sub(self.start)?;
+        if *self.generations.get(offset)? != h.generation {
+            return None;
+        }
+        return filled_mut(h.generation, &mut self.data[offset]);
+    }
+    /// Get an iterator yields &mut items live in this view's range.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item=&mut T> + '_ {
+        self.generations.iter()
+            .zip(self.data.iter_mut())
+            .filter_map(|(&generation, item)| filled_mut(generation, item))
+    }
+}
+
+/// Generational set: a GenVec<()> without the unit values, for
+/// tracking membership only. Each slot costs 17 bytes: its generation,
+/// its sequence number and its disabled flag, each in a packed vec.
+pub struct GenSet<A: Allocator = Global> {
+    genvec: GenVec<(), A>,
+}
+
+impl GenSet {
+    pub fn new() -> Self {
+        GenSet { genvec: GenVec::new() }
+    }
+}
+
+impl<A: Allocator + Clone> GenSet<A> {
+    pub fn new_in(alloc: A) -> Self {
+        GenSet { genvec: GenVec::new_in(alloc) }
+    }
+}
+
+impl<A: Allocator> GenSet<A> {
+    /// Add a member and get its handle.
+    pub fn insert(&mut self) -> EntryHandle<()> {
+        self.genvec.alloc(())
+    }
+    /// Remove a member. Returns false if h wasn't one.
+    pub fn remove(&mut self, h: EntryHandle<()>) -> bool {
+        self.genvec.strict_free(h).is_ok()
+    }
+    pub fn contains(&self, h: EntryHandle<()>) -> bool {
+        self.genvec.exists(h)
+    }
+    pub fn len(&self) -> usize {
+        self.genvec.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.genvec.is_empty()
+    }
+    /// Get an iterator yields every member's handle.
+    pub fn iter(&self) -> impl Iterator<Item=EntryHandle<()>> + '_ {
+        self.genvec.iter_with_handles().map(|(h, _)| h)
+    }
+}
+
+impl Default for GenSet {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Stable u32 ids for live elements of one GenVec, e.g. to send over
+/// the network instead of full handles. Ids count up from 0 and are
+/// never reused, so a stale id can't name a newer element. Allocate
+/// and free through the map to keep it in sync. S is the hasher of
+/// the internal maps, e.g. a faster non-DoS-resistant one.
+pub struct NetIdMap<T, S = RandomState> {
+    next_id: u32,
+    handles: HashMap<u32, EntryHandle<T>, S>,
+    ids: HashMap<EntryHandle<T>, u32, S>,
+}
+
+impl<T> NetIdMap<T> {
+    pub fn new() -> Self {
+        Self::with_hasher(RandomState::new())
+    }
+}
+
+impl<T, S: BuildHasher + Clone> NetIdMap<T, S> {
+    pub fn with_hasher(hasher: S) -> Self {
+        NetIdMap { next_id: 0, handles: HashMap::with_hasher(hasher.clone()), ids: HashMap::with_hasher(hasher) }
+    }
+}
+
+impl<T, S: BuildHasher> NetIdMap<T, S> {
+    /// Allocate in genvec and assign the element the next id.
+    /// Panics when u32 ids run out.
+    pub fn alloc<A: Allocator>(&mut self, genvec: &mut GenVec<T, A>, data: T) -> (EntryHandle<T>, u32) {
+        let Some(next_id) = self.next_id.checked_add(1) else {
+            panic!("Net ids exhausted: {:?}", self.next_id);
+        };
+        let id = self.next_id;
+        self.next_id = next_id;
+        let h = genvec.alloc(data);
+        self.handles.insert(id, h);
+        self.ids.insert(h, id);
+        return (h, id);
+    }
+    /// Free in genvec and release the element's id, which is returned.
+    pub fn free<A: Allocator>(&mut self, genvec: &mut GenVec<T, A>, h: EntryHandle<T>) -> Option<u32> {
+        genvec.free(h);
+        let id = self.ids.remove(&h)?;
+        self.handles.remove(&id);
+        return Some(id);
+    }
+    /// Handle for a live id, or None once it was released.
+    pub fn handle(&self, id: u32) -> Option<EntryHandle<T>> {
+        self.handles.get(&id).copied()
+    }
+    /// Id of a live element.
+    pub fn net_id(&self, h: EntryHandle<T>) -> Option<u32> {
+        self.ids.get(&h).copied()
+    }
+    /// Number of ids in use.
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+}
+
+impl<T, S: BuildHasher + Clone + Default> Default for NetIdMap<T, S> {
+    fn default() -> Self {
+        Self::with_hasher(S::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+
+    #[test]
+    fn btreemap_visits_handles_in_index_order() {
+        use std::collections::BTreeMap;
+        let mut genvec = GenVec::new();
+        let handles: Vec<_> = (0..4).map(|i| genvec.alloc(i)).collect();
+        genvec.free(handles[1]);
+        // Reusing index 1 gives it a higher generation than index 3
+        let reused = genvec.alloc(10);
+        assert_eq!(reused.index(), 1);
+        let mut map = BTreeMap::new();
+        for h in [handles[3], reused, handles[0], handles[2], handles[1]] {
+            map.insert(h, ());
+        }
+        let order: Vec<_> = map.keys().map(|h| (h.index(), h.generation())).collect();
+        assert_eq!(order, vec![(0, 0), (1, 0), (1, 2), (2, 0), (3, 0)]);
+        assert_eq!(handles[1].cmp_by_index(&reused), Ordering::Less);
+    }
+
+    #[test]
+    fn live_handle_set_matches_len() {
+        let mut genvec = GenVec::new();
+        let handles: Vec<_> = (0..6).map(|i| genvec.alloc(i)).collect();
+        genvec.free(handles[1]);
+        genvec.free(handles[4]);
+        let live = genvec.live_handle_set();
+        assert_eq!(live.len(), genvec.len());
+        assert!(!live.contains(&handles[1]));
+        assert!(!live.contains(&handles[4]));
+        assert!(live.contains(&handles[0]));
+    }
+
+    #[test]
+    fn alloc_at_takes_index_off_freelist() {
+        let mut genvec = GenVec::new();
+        let handles: Vec<_> = (0..4).map(|i| genvec.alloc(i)).collect();
+        genvec.free(handles[2]);
+        genvec.free(handles[0]);
+        let h = genvec.alloc_at(2, 20).unwrap();
+        assert_eq!((h.index(), h.generation()), (2, 2));
+        assert!(!genvec.freelist.contains(&2));
+        assert_eq!(genvec.index_ref(h), &20);
+        assert!(genvec.alloc_at(2, 21).is_none());
+        assert!(genvec.alloc_at(9, 21).is_none());
+    }
+
+    #[test]
+    fn values_sorted_by_key_ascending() {
+        let mut genvec = GenVec::new();
+        let handles: Vec<_> = [5, -1, 3, 0].iter().map(|&i| genvec.alloc(i)).collect();
+        let sorted = genvec.values_sorted_by_key(|&x| x);
+        let values: Vec<_> = sorted.iter().map(|&(_, &x)| x).collect();
+        assert_eq!(values, vec![-1, 0, 3, 5]);
+        for (h, &x) in sorted {
+            assert_eq!(genvec.index_ref(h), &x);
+        }
+        assert_eq!(genvec.values_sorted_by_key(|&x| x)[0].0, handles[1]);
+    }
+
+    #[test]
+    fn reserve_then_alloc_does_not_reallocate() {
+        let mut genvec = GenVec::new();
+        genvec.alloc(0);
+        genvec.reserve(100);
+        let capacity = genvec.capacity();
+        let generations = genvec.generations.as_ptr();
+        let data = genvec.data.as_ptr();
+        for i in 0..100 {
+            genvec.alloc(i);
+        }
+        assert_eq!(genvec.capacity(), capacity);
+        assert_eq!(genvec.generations.as_ptr(), generations);
+        assert_eq!(genvec.data.as_ptr(), data);
+    }
+
+    #[test]
+    fn max_and_min_by_key() {
+        let mut genvec = GenVec::new();
+        let handles: Vec<_> = [3, 9, -4, 7].iter().map(|&i| genvec.alloc(i)).collect();
+        assert_eq!(genvec.max_by_key(|&x| x), Some((handles[1], &9)));
+        assert_eq!(genvec.min_by_key(|&x| x), Some((handles[2], &-4)));
+        let empty: GenVec<i32> = GenVec::new();
+        assert!(empty.max_by_key(|&x| x).is_none());
+        assert!(empty.min_by_key(|&x| x).is_none());
+    }
+
+    #[test]
+    fn compact_with_patches_side_map() {
+        let mut genvec = GenVec::new();
+        let handles: Vec<_> = (0..6).map(|i| genvec.alloc(i)).collect();
+        genvec.free(handles[0]);
+        genvec.free(handles[3]);
+        // Stand-in for a SecondaryMap keyed by handles
+        let mut names: HashMap<EntryHandle<i32>, String> = handles.iter()
+            .filter(|h| genvec.exists(**h))
+            .map(|&h| (h, format!("n{}", genvec.index_ref(h))))
+            .collect();
+        genvec.compact_with(|old, new| {
+            let name = names.remove(&old).unwrap();
+            names.insert(new, name);
+        });
+        assert_eq!(names.len(), 4);
+        for (&h, name) in &names {
+            assert_eq!(name, &format!("n{}", genvec.index_ref(h)));
+        }
+    }
+
+    #[test]
+    fn get_copy_out_of_range_is_none() {
+        let mut genvec = GenVec::new();
+        let h = genvec.alloc(5u32);
+        assert_eq!(genvec.get_copy(h), Some(5));
+        let foreign = EntryHandle::from_parts(100, 0);
+        assert_eq!(genvec.get_copy(foreign), None);
+    }
+
+    #[test]
+    fn cursor_mut_frees_odd_values() {
+        let mut genvec = GenVec::new();
+        let handles: Vec<_> = (0..8).map(|i| genvec.alloc(i)).collect();
+        let mut cursor = genvec.cursor_mut();
+        while let Some(x) = cursor.next() {
+            if *x % 2 == 1 {
+                let h = cursor.current_handle().unwrap();
+                assert_eq!(h, handles[h.index()]);
+                cursor.free_current();
+            }
+        }
+        assert_eq!(genvec.copied().collect::<Vec<_>>(), vec![0, 2, 4, 6]);
+        assert!(!genvec.exists(handles[1]));
+    }
+
+    #[test]
+    fn reused_slot_is_same_slot_but_not_equal() {
+        let mut genvec = GenVec::new();
+        let old = genvec.alloc(1);
+        let other = genvec.alloc(2);
+        genvec.free(old);
+        let new = genvec.alloc(3);
+        assert!(new.same_slot(&old));
+        assert_ne!(new, old);
+        assert!(!new.same_slot(&other));
+    }
+
+    #[test]
+    fn drain_into_moves_everything() {
+        let mut src = GenVec::new();
+        let handles: Vec<_> = (0..5).map(|i| src.alloc(i)).collect();
+        src.free(handles[2]);
+        let mut dst = GenVec::new();
+        let kept = dst.alloc(100);
+        let remap = src.drain_into(&mut dst);
+        assert!(src.is_empty());
+        assert_eq!(dst.len(), 5);
+        assert_eq!(remap.len(), 4);
+        for (old, new) in remap {
+            assert!(!src.exists(old));
+            assert_eq!(dst.index_ref(new), &handles[old.index()].index());
+        }
+        assert_eq!(dst.index_ref(kept), &100);
+    }
+
+    #[test]
+    fn zero_sized_values_make_a_generational_set() {
+        let mut set: GenVec<()> = GenVec::new();
+        let handles: Vec<_> = (0..1000).map(|_| set.alloc(())).collect();
+        for h in handles.iter().step_by(2) {
+            set.free(*h);
+        }
+        assert_eq!(set.len(), 500);
+        assert_eq!(set.iter().count(), 500);
+        assert!(!set.exists(handles[0]));
+        assert!(set.exists(handles[1]));
+        // Reuse still bumps generations
+        let reused = set.alloc(());
+        assert!(reused.same_slot(&handles[998]));
+        assert_ne!(reused, handles[998]);
+        // data's capacity is usize::MAX for a ZST, yet adds no bytes
+        assert_eq!(set.data.capacity(), usize::MAX);
+        let bookkeeping = size_of::<GenVec<()>>()
+            + set.generations.capacity() * size_of::<u64>()
+            + set.seqs.capacity() * size_of::<u64>()
+            + set.disabled.capacity() * size_of::<bool>()
+            + set.freelist.capacity() * size_of::<usize>()
+            + set.reserved.capacity() * size_of::<usize>();
+        assert_eq!(set.deep_memory_usage(|_| 0), bookkeeping);
+        assert!(set.capacity() >= 1000 && set.capacity() < usize::MAX);
+    }
+
+    #[test]
+    fn copied_sums_live_values() {
+        let mut genvec = GenVec::new();
+        let handles: Vec<_> = (1..=10).map(|i| genvec.alloc(i)).collect();
+        assert_eq!(genvec.copied().sum::<i32>(), 55);
+        genvec.free(handles[9]);
+        assert_eq!(genvec.copied().sum::<i32>(), 45);
+    }
+
+    #[test]
+    fn validate_all_flags_freed_handles() {
+        let mut genvec = GenVec::new();
+        let handles: Vec<_> = (0..4).map(|i| genvec.alloc(i)).collect();
+        genvec.free(handles[1]);
+        genvec.free(handles[3]);
+        let reused = genvec.alloc(9);
+        let mut query = handles.clone();
+        query.push(reused);
+        assert_eq!(genvec.validate_all(&query), vec![true, false, true, false, true]);
+    }
+
+    /// Counts its drops in a shared cell.
+    struct DropCounter(Rc<Cell<usize>>);
+
+    impl Drop for DropCounter {
+        fn drop(&mut self) {
+            self.0.set(self.0.get() + 1);
+        }
+    }
+
+    #[test]
+    fn drops_each_value_exactly_once() {
+        let drops = Rc::new(Cell::new(0));
+        {
+            let mut genvec = GenVec::new();
+            let handles: Vec<_> = (0..5).map(|_| genvec.alloc(DropCounter(drops.clone()))).collect();
+            genvec.free(handles[1]);
+            assert_eq!(drops.get(), 1);
+            // A double free drops nothing
+            genvec.free(handles[1]);
+            assert_eq!(drops.get(), 1);
+            // Reusing the freed slot doesn't drop the old value again
+            genvec.alloc(DropCounter(drops.clone()));
+            genvec.free(handles[3]);
+            assert_eq!(drops.get(), 2);
+        }
+        // Only the four live values drop with the GenVec
+        assert_eq!(drops.get(), 6);
+    }
+
+    #[test]
+    fn freshness_variants() {
+        let mut genvec = GenVec::new();
+        let h = genvec.alloc(1);
+        assert_eq!(genvec.freshness(h), Freshness::Valid);
+        genvec.free(h);
+        assert_eq!(genvec.freshness(h), Freshness::Stale);
+        genvec.alloc(2);
+        assert_eq!(genvec.freshness(h), Freshness::Stale);
+        assert_eq!(genvec.freshness(EntryHandle::from_parts(1, 0)), Freshness::OutOfRange);
+    }
+
+    #[test]
+    fn join_mut_yields_indices_live_in_both() {
+        #[derive(Debug, PartialEq)]
+        struct Position(f32);
+        #[derive(Debug, PartialEq)]
+        struct Velocity(f32);
+        let mut positions = GenVec::new();
+        let mut velocities = GenVec::new();
+        let p: Vec<_> = (0..4).map(|i| positions.alloc(Position(i as f32))).collect();
+        let v: Vec<_> = (0..5).map(|i| velocities.alloc(Velocity(i as f32))).collect();
+        positions.free(p[1]);
+        velocities.free(v[2]);
+        let mut joined = Vec::new();
+        for (index, position, velocity) in positions.join_mut(&mut velocities) {
+            position.0 += velocity.0;
+            joined.push(index);
+        }
+        assert_eq!(joined, vec![0, 3]);
+        assert_eq!(positions.index_ref(p[3]), &Position(6.0));
+        assert_eq!(positions.index_ref(p[2]), &Position(2.0));
+    }
+
+    #[test]
+    fn with_capacity_and_freelist_sizes_both() {
+        let genvec: GenVec<u8> = GenVec::with_capacity_and_freelist(64, 16);
+        assert!(genvec.capacity() >= 64);
+        assert!(genvec.freelist.capacity() >= 16);
+    }
+
+    #[test]
+    fn strict_free_reports_double_and_stale_frees() {
+        let mut genvec = GenVec::new();
+        let h = genvec.alloc(1);
+        assert_eq!(genvec.strict_free(h), Ok(()));
+        assert_eq!(genvec.strict_free(h), Err(DoubleFree { generation: 0, index: 0 }));
+        let reused = genvec.alloc(2);
+        assert_eq!(genvec.strict_free(h), Err(DoubleFree { generation: 0, index: 0 }));
+        let mismatched = EntryHandle::from_parts(0, reused.generation() + 2);
+        assert!(genvec.strict_free(mismatched).is_err());
+        assert!(genvec.exists(reused));
+        // A handle from a bigger arena errors instead of panicking
+        let foreign = EntryHandle::from_parts(10, 0);
+        assert_eq!(genvec.strict_free(foreign), Err(DoubleFree { generation: 0, index: 10 }));
+    }
+
+    #[test]
+    fn generation_histogram_counts_reuse() {
+        let mut genvec = GenVec::new();
+        for i in 0..3 {
+            genvec.alloc(i);
+        }
+        // Churn slot 2 twice, leaving it live at generation 4
+        for _ in 0..2 {
+            let h = genvec.handle_at(2).unwrap();
+            genvec.free(h);
+            genvec.alloc(9);
+        }
+        // Leave slot 1 free at generation 1
+        genvec.free(genvec.handle_at(1).unwrap());
+        let histogram = genvec.generation_histogram();
+        assert_eq!(histogram, BTreeMap::from([(0, 1), (1, 1), (4, 1)]));
+    }
+
+    #[test]
+    fn slotmap_keydata_roundtrip() {
+        for (index, generation) in [(0, 0), (7, 4), (u32::MAX as usize, u32::MAX as u64 - 1)] {
+            let h: EntryHandle<u8> = EntryHandle::from_parts(index, generation);
+            let keydata = h.to_slotmap_keydata();
+            assert_eq!(keydata >> 32, generation + 1);
+            assert_eq!(EntryHandle::from_slotmap_keydata(keydata), Some(h));
+        }
+        // Even versions are vacant slotmap keys
+        assert_eq!(EntryHandle::<u8>::from_slotmap_keydata(2 << 32), None);
+    }
+
+    #[test]
+    fn retain_report_returns_freed_handles() {
+        let mut genvec = GenVec::new();
+        let handles: Vec<_> = (0..7).map(|i| genvec.alloc(i)).collect();
+        let freed = genvec.retain_report(|&x| x % 2 == 0);
+        let odds: Vec<_> = handles.iter().copied().filter(|h| h.index() % 2 == 1).collect();
+        assert_eq!(freed, odds);
+        assert!(freed.iter().all(|&h| !genvec.exists(h)));
+        assert_eq!(genvec.copied().collect::<Vec<_>>(), vec![0, 2, 4, 6]);
+    }
+
+    #[test]
+    fn dedup_by_key_keeps_lowest_index() {
+        let mut genvec = GenVec::new();
+        let handles: Vec<_> = ["a1", "b1", "a2", "c1", "b2", "a3"].iter().map(|&s| genvec.alloc(s)).collect();
+        let freed = genvec.dedup_by_key(|s| s.as_bytes()[0]);
+        assert_eq!(freed, 3);
+        assert_eq!(genvec.copied().collect::<Vec<_>>(), vec!["a1", "b1", "c1"]);
+        assert!(genvec.exists(handles[0]) && !genvec.exists(handles[5]));
+    }
+
+    #[test]
+    fn from_slots_roundtrips_iter_with_handles() {
+        let mut genvec = GenVec::new();
+        let handles: Vec<_> = (0..5).map(|i| genvec.alloc(i)).collect();
+        genvec.free(handles[1]);
+        genvec.free(handles[3]);
+        let reused = genvec.alloc(10);
+        let slots: Vec<_> = genvec.raw_slots().map(|(_, generation, item)| item.map(|&x| (generation, x))).collect();
+        let loaded = GenVec::from_slots(slots, genvec.freelist.clone()).unwrap();
+        let before: Vec<_> = genvec.iter_with_handles().map(|(h, &x)| (h, x)).collect();
+        let after: Vec<_> = loaded.iter_with_handles().map(|(h, &x)| (h, x)).collect();
+        assert_eq!(before, after);
+        assert_eq!(loaded.index_ref(reused), &10);
+        assert!(!loaded.exists(handles[1]));
+        assert_eq!(loaded.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn from_slots_rejects_inconsistent_input() {
+        let slots = || vec![Some((0, 'a')), None, Some((2, 'c')), None];
+        assert!(GenVec::from_slots(slots(), vec![3, 1]).is_ok());
+        assert_eq!(GenVec::from_slots(vec![Some((1, 'a'))], vec![]).unwrap_err(),
+            FromSlotsError::OddGeneration { generation: 1, index: 0 });
+        assert_eq!(GenVec::from_slots(slots(), vec![3, 1, 4]).unwrap_err(),
+            FromSlotsError::FreelistOutOfRange { index: 4 });
+        assert_eq!(GenVec::from_slots(slots(), vec![3, 1, 2]).unwrap_err(),
+            FromSlotsError::LiveOnFreelist { generation: 2, index: 2 });
+        assert_eq!(GenVec::from_slots(slots(), vec![3, 1, 3]).unwrap_err(),
+            FromSlotsError::FreelistDuplicate { index: 3 });
+        assert_eq!(GenVec::from_slots(slots(), vec![3]).unwrap_err(),
+            FromSlotsError::MissingFromFreelist { index: 1 });
+    }
+
+    #[test]
+    fn raw_slots_shows_holes() {
+        let mut genvec = GenVec::new();
+        let handles: Vec<_> = (0..3).map(|i| genvec.alloc(i)).collect();
+        genvec.free(handles[1]);
+        let slots: Vec<_> = genvec.raw_slots().collect();
+        assert_eq!(slots, vec![(0, 0, Some(&0)), (1, 1, None), (2, 0, Some(&2))]);
+    }
 
+    #[test]
+    fn fill_all_default_fills_every_free_slot() {
+        let mut genvec: GenVec<u32> = GenVec::new();
+        let handles: Vec<_> = (1..=6).map(|i| genvec.alloc(i)).collect();
+        for &h in &handles[1..4] {
+            genvec.free(h);
+        }
+        let filled = genvec.fill_all_default();
+        assert_eq!(filled.len(), 3);
+        assert_eq!(genvec.len(), 6);
+        assert!(genvec.freelist.is_empty());
+        for h in filled {
+            assert_eq!(genvec.index_ref(h), &0);
+        }
+    }
+
+    #[test]
+    fn assert_all_freed_passes_when_empty() {
+        let mut genvec = GenVec::new();
+        let h = genvec.alloc(1);
+        genvec.free(h);
+        genvec.assert_all_freed();
+    }
+
+    #[test]
+    #[should_panic(expected = "Leaked handles")]
+    fn assert_all_freed_panics_on_leak() {
+        let mut genvec = GenVec::new();
+        genvec.alloc(1);
+        genvec.assert_all_freed();
+    }
+
+    #[test]
+    fn contiguous_slice_only_without_holes() {
+        let mut genvec = GenVec::new();
+        let handles: Vec<_> = (0..4).map(|i| genvec.alloc(i)).collect();
+        assert_eq!(genvec.as_contiguous_slice(), Some(&[0, 1, 2, 3][..]));
+        genvec.free(handles[2]);
+        assert!(genvec.as_contiguous_slice().is_none());
+        genvec.alloc(5);
+        assert_eq!(genvec.as_contiguous_slice(), Some(&[0, 1, 5, 3][..]));
+    }
+
+    #[test]
+    fn parallel_storage_matches_simple_model() {
+        // Model: a Vec<Option<value>> plus the current generation per slot
+        let mut genvec = GenVec::new();
+        let mut model: Vec<Option<u32>> = Vec::new();
+        let mut handles = Vec::new();
+        for step in 0..200u32 {
+            if step % 3 == 2 && !handles.is_empty() {
+                let h: EntryHandle<u32> = handles.remove((step as usize * 7) % handles.len());
+                genvec.free(h);
+                model[h.index()] = None;
+            } else {
+                let h = genvec.alloc(step);
+                if h.index() == model.len() {
+                    model.push(None);
+                }
+                model[h.index()] = Some(step);
+                handles.push(h);
+            }
+        }
+        for h in &handles {
+            assert_eq!(genvec.get_ref(*h), model[h.index()].as_ref());
+            *genvec.get_mut(*h).unwrap() += 1;
+        }
+        let expected: Vec<_> = model.iter().flatten().map(|x| x + 1).collect();
+        assert_eq!(genvec.copied().collect::<Vec<_>>(), expected);
+        assert_eq!(genvec.len(), handles.len());
+    }
+
+    #[test]
+    fn get_copy_batch_mixed_handles() {
+        let mut genvec = GenVec::new();
+        let handles: Vec<_> = (0..4).map(|i| genvec.alloc(i * 10)).collect();
+        genvec.free(handles[2]);
+        let mut out = vec![Some(-1)];
+        let query = [handles[0], handles[2], handles[3], EntryHandle::from_parts(9, 0)];
+        genvec.get_copy_batch(&query, &mut out);
+        assert_eq!(out, vec![Some(-1), Some(0), None, Some(30), None]);
+    }
+
+    #[test]
+    fn reset_dense_restarts_at_index_zero() {
+        let mut genvec = GenVec::new();
+        let handles: Vec<_> = (0..5).map(|i| genvec.alloc(i)).collect();
+        genvec.free(handles[3]);
+        genvec.reset_dense();
+        assert!(genvec.is_empty());
+        assert!(handles.iter().all(|&h| !genvec.exists(h)));
+        let indices: Vec<_> = (0..5).map(|i| genvec.alloc(i).index()).collect();
+        assert_eq!(indices, vec![0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn raw_index_reads_and_writes() {
+        let mut genvec = GenVec::new();
+        let handles: Vec<_> = (0..3).map(|i| genvec.alloc(i)).collect();
+        *genvec.raw_index_mut(1) += 10;
+        assert_eq!(genvec.raw_index(1), &11);
+        assert_eq!(genvec.index_ref(handles[1]), &11);
+    }
+
+    #[test]
+    #[should_panic]
+    fn raw_index_panics_on_free_slot() {
+        let mut genvec = GenVec::new();
+        let h = genvec.alloc(1);
+        genvec.free(h);
+        genvec.raw_index(0);
+    }
+
+    #[test]
+    fn callbacks_count_allocs_and_frees() {
+        let allocs = Arc::new(AtomicUsize::new(0));
+        let frees = Arc::new(AtomicUsize::new(0));
+        let mut genvec = GenVec::new();
+        let counter = allocs.clone();
+        genvec.set_on_alloc(Some(Box::new(move |_| { counter.fetch_add(1, AtomicOrdering::SeqCst); })));
+        let counter = frees.clone();
+        genvec.set_on_free(Some(Box::new(move |_| { counter.fetch_add(1, AtomicOrdering::SeqCst); })));
+        let a = genvec.alloc(1);
+        genvec.alloc(2);
+        genvec.free(a);
+        genvec.free(a);
+        genvec.alloc(3);
+        genvec.retain(|&x| x != 2);
+        assert_eq!(allocs.load(AtomicOrdering::SeqCst), 3);
+        assert_eq!(frees.load(AtomicOrdering::SeqCst), 2);
+        genvec.set_on_free(None);
+        genvec.free(genvec.handle_at(0).unwrap());
+        assert_eq!(frees.load(AtomicOrdering::SeqCst), 2);
+    }
+
+    #[test]
+    fn from_parts_roundtrip() {
+        let mut genvec = GenVec::new();
+        let old = genvec.alloc('a');
+        genvec.free(old);
+        let h = genvec.alloc('b');
+        let (index, generation) = (h.index(), h.generation());
+        let rebuilt = EntryHandle::from_parts(index, generation);
+        assert_eq!(rebuilt, h);
+        assert_eq!(genvec.get_ref(rebuilt), Some(&'b'));
+        assert_eq!(genvec.get_ref(EntryHandle::from_parts(old.index(), old.generation())), None);
+    }
+
+    #[test]
+    fn iter_mut_with_rest_reads_others() {
+        let mut genvec = GenVec::new();
+        let handles: Vec<_> = [1, 2, 3, 4].iter().map(|&i| genvec.alloc(i)).collect();
+        genvec.free(handles[2]);
+        let original_total: i32 = genvec.iter().sum();
+        let mut walk = genvec.iter_mut_with_rest();
+        while let Some((item, rest)) = walk.next() {
+            let others: i32 = rest.iter().sum();
+            assert!(rest.iter().count() == 2);
+            *item = others;
+        }
+        // Each element saw the others as already updated or original
+        assert_eq!(genvec.index_ref(handles[0]), &(original_total - 1));
+        assert_eq!(genvec.index_ref(handles[1]), &(genvec.index_ref(handles[0]) + 4));
+        assert!(genvec.get_ref(handles[2]).is_none());
+    }
+
+    #[test]
+    fn is_valid_in_differs_between_clones() {
+        let mut genvec = GenVec::new();
+        let h = genvec.alloc(String::from("a"));
+        let mut copy = genvec.clone();
+        assert!(h.is_valid_in(&genvec) && h.is_valid_in(&copy));
+        copy.free(h);
+        copy.alloc(String::from("b"));
+        assert!(h.is_valid_in(&genvec));
+        assert!(!h.is_valid_in(&copy));
+    }
+
+    #[test]
+    fn debug_prints_only_live_elements() {
+        let mut genvec = GenVec::new();
+        let handles: Vec<_> = ["keep", "gone", "also"].iter().map(|&s| genvec.alloc(s)).collect();
+        genvec.free(handles[1]);
+        let printed = format!("{:?}", genvec);
+        assert!(printed.starts_with("GenVec (2 live / 3 slots / 1 free)"));
+        assert!(printed.contains("\"keep\"") && printed.contains("\"also\""));
+        assert!(!printed.contains("gone"));
+    }
+
+    #[test]
+    fn split_ranges_mut_across_scoped_threads() {
+        let mut genvec = GenVec::new();
+        let handles: Vec<_> = (0..10).map(|i| genvec.alloc(i)).collect();
+        genvec.free(handles[4]);
+        std::thread::scope(|scope| {
+            for mut view in genvec.split_ranges_mut(3) {
+                scope.spawn(move || {
+                    let start = view.range().start as i32;
+                    for item in view.iter_mut() {
+                        *item += 100 * start;
+                    }
+                });
+            }
+        });
+        assert_eq!(genvec.copied().collect::<Vec<_>>(), vec![0, 1, 2, 303, 305, 606, 607, 608, 609]);
+        let mut views = genvec.split_ranges_mut(3);
+        assert!(views[0].get_mut(handles[5]).is_none());
+        assert!(views[1].get_mut(handles[5]).is_some());
+        assert!(views[1].get_mut(handles[4]).is_none());
+    }
+
+    #[test]
+    fn restore_revives_handles_from_snapshot() {
+        let mut genvec = GenVec::new();
+        let h = genvec.alloc(String::from("a"));
+        let snapshot = genvec.snapshot();
+        genvec.free(h);
+        let newer = genvec.alloc(String::from("b"));
+        assert!(!genvec.exists(h));
+        genvec.restore(snapshot);
+        assert_eq!(genvec.get_ref(h).map(|s| s.as_str()), Some("a"));
+        assert!(!genvec.exists(newer));
+        assert_eq!(genvec.len(), 1);
+    }
+
+    #[test]
+    fn live_high_index_drops_while_high_water_mark_stays() {
+        let mut genvec = GenVec::new();
+        assert_eq!(genvec.live_high_index(), None);
+        let handles: Vec<_> = (0..4).map(|i| genvec.alloc(i)).collect();
+        assert_eq!((genvec.high_water_mark(), genvec.live_high_index()), (4, Some(3)));
+        genvec.free(handles[3]);
+        genvec.free(handles[2]);
+        assert_eq!((genvec.high_water_mark(), genvec.live_high_index()), (4, Some(1)));
+    }
+
+    #[test]
+    fn rehandle_invalidates_old_handle() {
+        let mut genvec = GenVec::new();
+        let h = genvec.alloc(String::from("a"));
+        genvec.alloc(String::from("b"));
+        let new = genvec.rehandle(h).unwrap();
+        assert_ne!(new, h);
+        assert!(!genvec.exists(h));
+        assert_eq!(genvec.index_ref(new), "a");
+        assert_eq!(genvec.len(), 2);
+        assert!(genvec.rehandle(h).is_none());
+    }
+
+    /// Global, counting live allocations into its own counter.
+    #[derive(Clone, Copy)]
+    struct CountingAlloc<'a>(&'a AtomicUsize);
+
+    unsafe impl Allocator for CountingAlloc<'_> {
+        fn allocate(&self, layout: std::alloc::Layout) -> Result<std::ptr::NonNull<[u8]>, std::alloc::AllocError> {
+            self.0.fetch_add(1, AtomicOrdering::SeqCst);
+            return Global.allocate(layout);
+        }
+        unsafe fn deallocate(&self, ptr: std::ptr::NonNull<u8>, layout: std::alloc::Layout) {
+            self.0.fetch_sub(1, AtomicOrdering::SeqCst);
+            unsafe { Global.deallocate(ptr, layout) }
+        }
+    }
+
+    #[test]
+    fn each_arena_uses_its_own_allocator() {
+        let first_count = AtomicUsize::new(0);
+        let second_count = AtomicUsize::new(0);
+        {
+            let mut first = GenVec::new_in(CountingAlloc(&first_count));
+            let mut second = GenVec::with_capacity_in(1, CountingAlloc(&second_count));
+            let a = first.alloc(String::from("a"));
+            first.alloc(String::from("b"));
+            first.free(a);
+            assert_eq!(first.iter().count(), 1);
+            let before = first_count.load(AtomicOrdering::SeqCst);
+            for i in 0..100 {
+                second.alloc(i.to_string());
+            }
+            // Growing the second arena doesn't touch the first allocator
+            assert_eq!(first_count.load(AtomicOrdering::SeqCst), before);
+            assert!(second_count.load(AtomicOrdering::SeqCst) > 0);
+            let copy = second.clone();
+            assert_eq!(copy.len(), 100);
+        }
+        // Every backing vec went back to the allocator it came from
+        assert_eq!(first_count.load(AtomicOrdering::SeqCst), 0);
+        assert_eq!(second_count.load(AtomicOrdering::SeqCst), 0);
+    }
+
+    #[test]
+    fn reserve_many_then_fill_from_threads() {
+        let mut genvec = GenVec::new();
+        let freed = genvec.alloc(0);
+        genvec.alloc(1);
+        genvec.free(freed);
+        let handles = genvec.reserve_many(10);
+        assert_eq!(genvec.len(), 1);
+        assert!(handles.iter().all(|&h| !genvec.exists(h)));
+        let values: Vec<_> = std::thread::scope(|scope| {
+            let workers: Vec<_> = (0..handles.len()).map(|i| scope.spawn(move || i * 10)).collect();
+            workers.into_iter().map(|worker| worker.join().unwrap()).collect()
+        });
+        for (&h, value) in handles.iter().zip(values) {
+            assert!(genvec.fill(h, value).is_ok());
+        }
+        assert!(genvec.fill(handles[0], 0).is_err());
+        assert_eq!(genvec.len(), 11);
+        let read: Vec<_> = handles.iter().map(|&h| *genvec.index_ref(h)).collect();
+        assert_eq!(read, (0..10).map(|i| i * 10).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn changed_handles_since_snapshot() {
+        let mut genvec = GenVec::new();
+        let a = genvec.alloc(1);
+        let b = genvec.alloc(2);
+        let previous = genvec.clone();
+        genvec.free(a);
+        let c = genvec.alloc(3);
+        let d = genvec.alloc(4);
+        let (added, removed) = genvec.changed_handles(&previous);
+        assert_eq!(added, vec![c, d]);
+        assert_eq!(removed, vec![a]);
+        assert!(!added.contains(&b) && !removed.contains(&b));
+    }
+
+    #[test]
+    fn live_windows_skip_freed_slots() {
+        let mut genvec = GenVec::new();
+        let handles: Vec<_> = (0..7).map(|i| genvec.alloc(i)).collect();
+        genvec.free(handles[1]);
+        genvec.free(handles[4]);
+        let windows: Vec<Vec<i32>> = genvec.live_windows(3).map(|w| w.into_iter().copied().collect()).collect();
+        assert_eq!(windows, vec![vec![0, 2, 3], vec![2, 3, 5], vec![3, 5, 6]]);
+        assert_eq!(genvec.live_windows(6).count(), 0);
+    }
+
+    #[test]
+    fn gc_collects_unreachable_cycle() {
+        // Nodes hold their neighbours' handle parts
+        let mut graph: GenVec<Vec<(usize, u64)>> = GenVec::new();
+        let root = graph.alloc(vec![]);
+        let child = graph.alloc(vec![]);
+        let x = graph.alloc(vec![]);
+        let y = graph.alloc(vec![]);
+        graph.get_mut(root).unwrap().push((child.index(), child.generation()));
+        graph.get_mut(child).unwrap().push((root.index(), root.generation()));
+        graph.get_mut(x).unwrap().push((y.index(), y.generation()));
+        graph.get_mut(y).unwrap().push((x.index(), x.generation()));
+        let freed = graph.gc(&[root], |node| node.iter().map(|&(index, generation)| EntryHandle::from_parts(index, generation)).collect());
+        assert_eq!(freed, 2);
+        assert!(graph.exists(root) && graph.exists(child));
+        assert!(!graph.exists(x) && !graph.exists(y));
+    }
+
+    #[test]
+    fn wrap_with_epoch_never_revalidates_old_handles() {
+        // A limit of 256 keeps generation % limit in a u8
+        let mut genvec = GenVec::new();
+        genvec.set_generation_limit(Some((256, GenerationWrap::WrapWithEpoch)));
+        let first = genvec.alloc(0u32);
+        let mut seen = HashSet::from([first]);
+        let mut h = first;
+        for i in 0..300 {
+            genvec.free(h);
+            h = genvec.alloc(i);
+            assert_eq!(h.index(), 0);
+            assert!(seen.insert(h), "Handle reissued: {:?}", (h.generation(), h.index()));
+            assert!(seen.iter().filter(|&&old| genvec.exists(old)).count() == 1);
+        }
+        assert_eq!(genvec.epoch(), 2);
+        assert!(!genvec.exists(first));
+        assert!(h.generation() > 256);
+    }
+
+    /// Slot 1 at generation 2 with slot 0 free, under a limit of 4.
+    fn retire_compaction_fixture() -> (GenVec<i32>, EntryHandle<i32>) {
+        let mut genvec = GenVec::new();
+        genvec.set_generation_limit(Some((4, GenerationWrap::Retire)));
+        let a = genvec.alloc(0);
+        let b = genvec.alloc(1);
+        genvec.free(b);
+        let b = genvec.alloc(2);
+        assert_eq!((b.index(), b.generation()), (1, 2));
+        genvec.free(a);
+        return (genvec, b);
+    }
+
+    #[test]
+    fn compaction_counts_vacated_slot_hitting_limit() {
+        // Moving slot 1 down leaves it at generation 3, which retires it
+        let (mut genvec, b) = retire_compaction_fixture();
+        let remap = genvec.compact();
+        assert_eq!(remap.len(), 1);
+        assert_eq!(remap[0].0, b);
+        assert_eq!(genvec.retired_count(), 1);
+        assert_eq!(genvec.len(), 1);
+        assert_eq!(genvec.check_invariants(), Ok(()));
+        // The retired slot comes back through reclaim_retired, not alloc
+        assert_eq!(genvec.alloc(5).index(), 2);
+        assert_eq!(genvec.reclaim_retired(|_, _| {}), 1);
+        assert_eq!(genvec.check_invariants(), Ok(()));
+
+        let (mut genvec, _) = retire_compaction_fixture();
+        genvec.compact_and_shrink();
+        assert_eq!((genvec.retired_count(), genvec.len()), (1, 1));
+        assert_eq!(genvec.check_invariants(), Ok(()));
+
+        let (mut genvec, _) = retire_compaction_fixture();
+        assert!(genvec.maybe_compact(0.0).is_some());
+        assert_eq!((genvec.retired_count(), genvec.len()), (1, 1));
+        assert_eq!(genvec.check_invariants(), Ok(()));
+
+        let (mut genvec, _) = retire_compaction_fixture();
+        let mut walk = genvec.iter_compacting_mut();
+        while let Some((_, item)) = walk.next() {
+            *item += 1;
+        }
+        assert_eq!(walk.finish().len(), 1);
+        assert_eq!((genvec.retired_count(), genvec.len()), (1, 1));
+        assert_eq!(genvec.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn iter_with_handles_rev_reverses_order() {
+        let mut genvec = GenVec::new();
+        let handles: Vec<_> = (0..6).map(|i| genvec.alloc(i)).collect();
+        genvec.free(handles[0]);
+        genvec.free(handles[3]);
+        let mut forward: Vec<_> = genvec.iter_with_handles().map(|(h, &x)| (h, x)).collect();
+        forward.reverse();
+        let backward: Vec<_> = genvec.iter_with_handles_rev().map(|(h, &x)| (h, x)).collect();
+        assert_eq!(backward, forward);
+        assert_eq!(backward.first().map(|&(h, _)| h), Some(handles[5]));
+    }
+
+    #[test]
+    fn try_get_disjoint_mut_error_variants() {
+        let mut genvec = GenVec::new();
+        let a = genvec.alloc(1);
+        let b = genvec.alloc(2);
+        let c = genvec.alloc(3);
+        genvec.free(c);
+        let [x, y] = genvec.try_get_disjoint_mut([a, b]).unwrap();
+        std::mem::swap(x, y);
+        assert_eq!(genvec.get_copy(a), Some(2));
+        assert_eq!(genvec.try_get_disjoint_mut([a, b, a]).err(), Some(DisjointError::Overlap { positions: (0, 2) }));
+        assert_eq!(genvec.try_get_disjoint_mut([b, a, b]).err(), Some(DisjointError::Overlap { positions: (0, 2) }));
+        assert_eq!(genvec.try_get_disjoint_mut([a, c]).err(), Some(DisjointError::Stale { position: 1 }));
+    }
+
+    /// Live values by a plain slot walk, without the dense fast path.
+    fn slow_values(genvec: &GenVec<i32>) -> Vec<i32> {
+        genvec.raw_slots().filter_map(|(_, _, item)| item.copied()).collect()
+    }
+
+    #[test]
+    fn dense_fast_path_matches_slow_path() {
+        let mut genvec = GenVec::new();
+        let handles: Vec<_> = (0..100).map(|i| genvec.alloc(i)).collect();
+        assert_eq!(genvec.dense_prefix, 100);
+        assert_eq!(genvec.iter().copied().collect::<Vec<_>>(), slow_values(&genvec));
+        for h in handles.iter().step_by(7) {
+            genvec.free(*h);
+        }
+        assert_eq!(genvec.dense_prefix, 0);
+        assert_eq!(genvec.iter().copied().collect::<Vec<_>>(), slow_values(&genvec));
+        genvec.alloc(1000);
+        genvec.alloc(1001);
+        assert_eq!(genvec.iter().copied().collect::<Vec<_>>(), slow_values(&genvec));
+        // Compaction closes every hole, so the fast path covers everything again
+        genvec.compact();
+        assert_eq!(genvec.dense_prefix, genvec.len());
+        for item in genvec.iter_mut() {
+            *item += 1;
+        }
+        assert_eq!(genvec.iter().copied().collect::<Vec<_>>(), slow_values(&genvec));
+    }
+
+    #[test]
+    fn dense_fast_path_over_large_arena() {
+        let mut genvec = GenVec::new();
+        for i in 0..1_000_000u64 {
+            genvec.alloc(i);
+        }
+        // Entirely on the fast path: no per-slot generation checks
+        assert_eq!(genvec.dense_prefix, genvec.len());
+        assert_eq!(genvec.iter().sum::<u64>(), 999_999 * 1_000_000 / 2);
+    }
+
+    #[test]
+    fn count_where_counts_matches() {
+        let mut genvec = GenVec::new();
+        let handles: Vec<_> = (0..10).map(|i| genvec.alloc(i)).collect();
+        assert_eq!(genvec.count_where(|x| x % 2 == 0), 5);
+        genvec.free(handles[4]);
+        assert_eq!(genvec.count_where(|x| x % 2 == 0), 4);
+    }
+
+    #[test]
+    fn maybe_compact_respects_threshold() {
+        let mut genvec = GenVec::new();
+        let handles: Vec<_> = (0..10).map(|i| genvec.alloc(i)).collect();
+        genvec.free(handles[0]);
+        assert!((genvec.fragmentation() - 0.1).abs() < 1e-9);
+        assert!(genvec.maybe_compact(0.5).is_none());
+        assert!(genvec.exists(handles[1]));
+        for &h in &handles[1..6] {
+            genvec.free(h);
+        }
+        let remap = genvec.maybe_compact(0.5).unwrap();
+        assert_eq!(remap.len(), 4);
+        assert_eq!(genvec.fragmentation(), 0.0);
+        assert!(!genvec.exists(handles[6]));
+    }
+
+    #[test]
+    fn sort_freelist_gives_ascending_reuse() {
+        let mut genvec = GenVec::new();
+        let handles: Vec<_> = (0..8).map(|i| genvec.alloc(i)).collect();
+        for i in [5, 1, 7, 3] {
+            genvec.free(handles[i]);
+        }
+        let generations = genvec.generations.clone();
+        genvec.sort_freelist();
+        assert_eq!(genvec.generations, generations);
+        let indices: Vec<_> = (0..4).map(|i| genvec.alloc(i).index()).collect();
+        assert_eq!(indices, vec![1, 3, 5, 7]);
+    }
+
+    #[test]
+    fn ensure_index_grows_with_free_slots() {
+        let mut genvec = GenVec::new();
+        let h = genvec.ensure_index(5);
+        assert_eq!(h.index(), 5);
+        assert_eq!(genvec.high_water_mark(), 6);
+        assert_eq!(genvec.len(), 0);
+        assert!((0..5).all(|index| !genvec.is_live(index) && genvec.freelist.contains(&index)));
+        assert_eq!(genvec.ensure_index(5), h);
+        assert!(genvec.fill(h, 50).is_ok());
+        let indices: Vec<_> = (0..5).map(|i| genvec.alloc(i).index()).collect();
+        assert_eq!(indices, vec![0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn free_count_tracks_reusable_slots() {
+        let mut genvec = GenVec::with_capacity(100);
+        let handles: Vec<_> = (0..5).map(|i| genvec.alloc(i)).collect();
+        for &h in &handles[..3] {
+            genvec.free(h);
+        }
+        assert_eq!(genvec.free_count(), 3);
+        genvec.alloc(9);
+        assert_eq!(genvec.free_count(), 2);
+    }
+
+    #[test]
+    fn cast_reads_mapped_values() {
+        let mut genvec = GenVec::new();
+        let handles: Vec<_> = (0..5).map(|i| genvec.alloc(i)).collect();
+        genvec.free(handles[2]);
+        let mapped: GenVec<String> = genvec.map(|x| format!("n{}", x));
+        assert_eq!(mapped.get_ref(handles[3].cast()).unwrap(), "n3");
+        assert!(mapped.get_ref(handles[2].cast::<String>()).is_none());
+        let h: EntryHandle<String> = handles[4].cast();
+        assert_eq!((h.index(), h.generation()), (handles[4].index(), handles[4].generation()));
+    }
+
+    #[test]
+    fn drain_sorted_by_key_empties_in_order() {
+        let mut genvec = GenVec::new();
+        for x in [5, -2, 9, 0, 3] {
+            genvec.alloc(x);
+        }
+        let capacity = genvec.capacity();
+        let drained: Vec<i32> = genvec.drain_sorted_by_key(|&x| x).collect();
+        assert_eq!(drained, vec![-2, 0, 3, 5, 9]);
+        assert!(genvec.is_empty());
+        assert_eq!(genvec.capacity(), capacity);
+    }
+
+    #[test]
+    fn check_invariants_passes_normal_arena() {
+        let mut genvec = GenVec::new();
+        let handles: Vec<_> = (0..5).map(|i| genvec.alloc(i)).collect();
+        genvec.free(handles[1]);
+        genvec.reserve_handle();
+        genvec.free(handles[3]);
+        genvec.disable(handles[4]);
+        assert_eq!(genvec.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn check_invariants_reports_corrupt_freelist() {
+        // from_slots rejects these freelists, so corrupt one directly
+        let slots = || vec![Some((0, 1)), None, None];
+        assert!(GenVec::from_slots(slots(), vec![1, 2, 1]).is_err());
+        let mut genvec = GenVec::from_slots(slots(), vec![1, 2]).unwrap();
+        genvec.freelist.push(1);
+        assert_eq!(genvec.check_invariants(), Err("Index on freelist twice: 1".to_string()));
+        genvec.freelist = vec![2, 1, 0];
+        assert_eq!(genvec.check_invariants(), Err("Live slot on freelist: (0, 0)".to_string()));
+        genvec.freelist = vec![2, 1, 3];
+        assert_eq!(genvec.check_invariants(), Err("Freelist index out of bounds: 3".to_string()));
+        genvec.freelist = vec![2];
+        assert_eq!(genvec.check_invariants(), Err("Free slots unaccounted for: (2, 1, 0, 0)".to_string()));
+    }
+
+    #[test]
+    fn apply_ops_replays_deterministically() {
+        let ops = [Op::Alloc(10), Op::Alloc(20), Op::Free(0), Op::Get(0), Op::Get(1),
+            Op::Free(0), Op::Alloc(30), Op::Get(2), Op::Free(7)];
+        let mut genvec = GenVec::new();
+        let log = genvec.apply_ops(&ops);
+        assert_eq!(log[2], OpResult::Freed(true));
+        assert_eq!(log[3], OpResult::Got(None));
+        assert_eq!(log[4], OpResult::Got(Some(20)));
+        assert_eq!(log[5], OpResult::Freed(false));
+        assert_eq!(log[7], OpResult::Got(Some(30)));
+        assert_eq!(log[8], OpResult::Freed(false));
+        assert_eq!(genvec.iter().copied().collect::<Vec<_>>(), vec![30, 20]);
+        // The same ops give the same log on a fresh arena
+        assert_eq!(GenVec::new().apply_ops(&ops), log);
+    }
+
+    #[test]
+    fn pairs_mut_yields_each_pair_once() {
+        let mut genvec = GenVec::new();
+        let handles: Vec<_> = (0..4).map(|i| genvec.alloc(vec![i])).collect();
+        genvec.free(handles[1]);
+        let mut pairs = genvec.pairs_mut();
+        let mut seen = vec![];
+        while let Some((a, b)) = pairs.next() {
+            assert!(!std::ptr::eq(a, b));
+            seen.push((a[0], b[0]));
+            a.push(b[0]);
+            b.push(a[0]);
+        }
+        assert_eq!(seen, vec![(0, 2), (0, 3), (2, 3)]);
+        assert_eq!(genvec.index_ref(handles[0]), &vec![0, 2, 3]);
+        let mut single = GenVec::new();
+        single.alloc(1);
+        assert!(single.pairs_mut().next().is_none());
+    }
+
+    #[test]
+    fn find_and_find_all_by_value() {
+        let mut genvec = GenVec::new();
+        let handles: Vec<_> = ["a", "b", "c", "b"].iter().map(|s| genvec.alloc(s.to_string())).collect();
+        let h = genvec.find(|s| s == "b").unwrap();
+        assert_eq!(h, handles[1]);
+        assert_eq!(genvec.index_ref(h), "b");
+        assert_eq!(genvec.find_all(|s| s == "b"), vec![handles[1], handles[3]]);
+        assert!(genvec.find(|s| s == "z").is_none());
+    }
+
+    #[test]
+    fn net_ids_follow_alloc_and_free() {
+        let mut genvec = GenVec::new();
+        let mut ids = NetIdMap::new();
+        let (a, a_id) = ids.alloc(&mut genvec, "a");
+        let (b, b_id) = ids.alloc(&mut genvec, "b");
+        assert_eq!((a_id, b_id), (0, 1));
+        assert_eq!(ids.handle(b_id), Some(b));
+        assert_eq!(genvec.get_ref(ids.handle(a_id).unwrap()), Some(&"a"));
+        assert_eq!(ids.free(&mut genvec, a), Some(0));
+        assert_eq!(ids.handle(0), None);
+        assert!(!genvec.exists(a));
+        // Ids aren't reused
+        let (_, c_id) = ids.alloc(&mut genvec, "c");
+        assert_eq!(c_id, 2);
+        assert_eq!(ids.len(), 2);
+    }
+
+    #[test]
+    fn compact_and_shrink_gives_memory_back() {
+        let mut genvec = GenVec::new();
+        let handles: Vec<_> = (0..10_000).map(|i| genvec.alloc(i)).collect();
+        for &h in &handles {
+            if h.index() % 100 != 0 {
+                genvec.free(h);
+            }
+        }
+        let before = genvec.capacity();
+        let remap = genvec.compact_and_shrink();
+        assert!(genvec.capacity() < before / 10);
+        assert_eq!(genvec.len(), 100);
+        for (old, new) in &remap {
+            assert_eq!(genvec.get_copy(*new), Some(old.index() as i32));
+        }
+        assert_eq!(genvec.check_invariants(), Ok(()));
+        // Slots pushed again later stay above the dropped generations
+        let stale = handles[150];
+        for i in 0..200 {
+            genvec.alloc(i);
+        }
+        assert!(!genvec.exists(stale));
+    }
+
+    /// Secret bytes that count their drops, to see the scrub runs after
+    /// the value is dropped.
+    struct Secret([u64; 4], Rc<Cell<usize>>);
+
+    impl Drop for Secret {
+        fn drop(&mut self) {
+            self.1.set(self.1.get() + 1);
+        }
+    }
+
+    #[test]
+    fn zero_on_free_scrubs_slot() {
+        let drops = Rc::new(Cell::new(0));
+        let mut genvec = GenVec::new();
+        genvec.set_zero_on_free(true);
+        let a = genvec.alloc(Secret([0xAAAA_AAAA; 4], drops.clone()));
+        let b = genvec.alloc(Secret([1; 4], drops.clone()));
+        genvec.free(a);
+        assert_eq!(drops.get(), 1);
+        let slot = &genvec.data[a.index()];
+        // SAFETY: the scrub wrote zeroes over every byte of the slot
+        let bytes = unsafe { std::slice::from_raw_parts(slot.as_ptr().cast::<u8>(), size_of::<Secret>()) };
+        assert!(bytes.iter().all(|&byte| byte == 0));
+        assert_eq!(genvec.index_ref(b).0, [1; 4]);
+    }
+
+    #[test]
+    fn handles_in_range_yields_live_slots_only() {
+        let mut genvec = GenVec::new();
+        let handles: Vec<_> = (0..10).map(|i| genvec.alloc(i)).collect();
+        genvec.free(handles[4]);
+        assert_eq!(genvec.handles_in_range(3..7).collect::<Vec<_>>(), vec![handles[3], handles[5], handles[6]]);
+        assert_eq!(genvec.handles_in_range(8..100).count(), 2);
+        assert_eq!(genvec.handles_in_range(50..100).count(), 0);
+    }
+
+    #[test]
+    fn join_index_mut_needs_both_live() {
+        let mut positions = GenVec::new();
+        let mut velocities = GenVec::new();
+        for i in 0..3 {
+            positions.alloc(i as f32);
+            velocities.alloc(1.0f32);
+        }
+        velocities.free(EntryHandle::from_parts(1, 0));
+        let (position, velocity) = join_index_mut(&mut positions, &mut velocities, 2).unwrap();
+        *position += *velocity;
+        assert_eq!(positions.raw_index(2), &3.0);
+        assert!(join_index_mut(&mut positions, &mut velocities, 1).is_none());
+        assert!(join_index_mut(&mut positions, &mut velocities, 9).is_none());
+    }
+
+    #[test]
+    fn clone_compact_packs_live_elements() {
+        let mut genvec = GenVec::new();
+        let handles: Vec<_> = (0..6).map(|i| genvec.alloc(i)).collect();
+        genvec.free(handles[0]);
+        genvec.free(handles[3]);
+        let (copy, remap) = genvec.clone_compact();
+        assert_eq!(copy.high_water_mark(), 4);
+        assert!(copy.freelist.is_empty());
+        assert!(copy.as_contiguous_slice().is_some());
+        assert_eq!(remap.len(), 4);
+        for h in [handles[1], handles[2], handles[4], handles[5]] {
+            assert_eq!(copy.get_copy(remap[&h]), genvec.get_copy(h));
+        }
+        assert_eq!(copy.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn try_for_each_mut_stops_at_first_error() {
+        let mut genvec = GenVec::new();
+        for i in 0..10 {
+            genvec.alloc(i);
+        }
+        let mut visited = 0;
+        let result = genvec.try_for_each_mut(|_, x| {
+            visited += 1;
+            if *x == 4 {
+                return Err(*x);
+            }
+            *x *= 10;
+            return Ok(());
+        });
+        assert_eq!(result, Err(4));
+        assert_eq!(visited, 5);
+        assert_eq!(genvec.iter().copied().collect::<Vec<_>>(), vec![0, 10, 20, 30, 4, 5, 6, 7, 8, 9]);
+    }
+
+    #[test]
+    fn iter_with_seq_gives_reused_slot_a_new_seq() {
+        let mut genvec = GenVec::new();
+        let a = genvec.alloc("a");
+        genvec.alloc("b");
+        genvec.alloc("c");
+        genvec.free(a);
+        let d = genvec.alloc("d");
+        assert_eq!(d.index(), 0);
+        let seqs: Vec<_> = genvec.iter_with_seq().map(|(seq, _, &x)| (seq, x)).collect();
+        assert_eq!(seqs, vec![(3, "d"), (1, "b"), (2, "c")]);
+        // Compaction keeps each element's seq
+        let b = genvec.iter_with_seq().nth(1).unwrap().1;
+        genvec.free(b);
+        genvec.compact();
+        let seqs: Vec<_> = genvec.iter_with_seq().map(|(seq, _, &x)| (seq, x)).collect();
+        assert_eq!(seqs, vec![(3, "d"), (2, "c")]);
+    }
+
+    #[test]
+    fn weak_handle_upgrade_before_and_after_free() {
+        let mut genvec = GenVec::new();
+        let h = genvec.alloc(5);
+        let weak = h.downgrade();
+        assert_eq!(weak.upgrade(&genvec), Some(&5));
+        *weak.upgrade_mut(&mut genvec).unwrap() = 6;
+        assert_eq!(WeakHandle::from(h).upgrade(&genvec), Some(&6));
+        genvec.free(h);
+        assert_eq!(weak.upgrade(&genvec), None);
+        assert_eq!(weak.handle(), h);
+    }
+
+    #[test]
+    fn try_alloc_batch_rolls_back_on_error() {
+        let mut genvec = GenVec::new();
+        let keep = genvec.alloc(1);
+        let result = genvec.try_alloc_batch(vec![Ok(2), Ok(3), Err("bad"), Ok(4)]);
+        assert_eq!(result, Err("bad"));
+        assert_eq!(genvec.len(), 1);
+        assert_eq!(genvec.iter().copied().collect::<Vec<_>>(), vec![1]);
+        assert!(genvec.exists(keep));
+        let handles = genvec.try_alloc_batch(vec![Ok::<_, ()>(5), Ok(6)]).unwrap();
+        assert_eq!(handles.iter().map(|&h| *genvec.index_ref(h)).collect::<Vec<_>>(), vec![5, 6]);
+    }
+
+    #[test]
+    fn get_ref_or_falls_back_for_freed_handle() {
+        let mut genvec = GenVec::new();
+        let live = genvec.alloc("real");
+        let gone = genvec.alloc("gone");
+        genvec.free(gone);
+        assert_eq!(*genvec.get_ref_or(live, &"placeholder"), "real");
+        assert_eq!(*genvec.get_ref_or(gone, &"placeholder"), "placeholder");
+    }
+
+    #[test]
+    fn zip_live_skips_index_free_in_either() {
+        let mut a = GenVec::new();
+        let mut b = GenVec::new();
+        let handles: Vec<_> = (0..4).map(|i| a.alloc(i)).collect();
+        for i in 0..5 {
+            b.alloc(i * 10);
+        }
+        a.free(handles[2]);
+        let zipped: Vec<_> = a.zip_live(&b).map(|(index, &x, &y)| (index, x, y)).collect();
+        assert_eq!(zipped, vec![(0, 0, 0), (1, 1, 10), (3, 3, 30)]);
+    }
+
+    #[test]
+    fn normalize_generations_remaps_to_minimal() {
+        let mut genvec = GenVec::new();
+        let handles: Vec<_> = (0..4).map(|i| genvec.alloc(i)).collect();
+        genvec.free(handles[0]);
+        let reused = genvec.alloc(10);
+        genvec.free(handles[2]);
+        let reservation = genvec.reserve_handle();
+        let remap: HashMap<_, _> = genvec.normalize_generations().into_iter().collect();
+        assert!(genvec.generations.iter().all(|&generation| generation <= 1));
+        assert!(genvec.iter_with_handles().all(|(h, _)| h.generation() == 0));
+        assert_eq!(genvec.get_copy(remap[&reused]), Some(10));
+        assert_eq!(genvec.get_copy(handles[1]), Some(1));
+        assert!(genvec.fill(*remap.get(&reservation).unwrap_or(&reservation), 7).is_ok());
+        assert_eq!(genvec.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn filtered_handles_selects_matches() {
+        let mut genvec = GenVec::new();
+        for i in 0..10 {
+            genvec.alloc(i);
+        }
+        let selected: Vec<_> = genvec.filtered_handles(|&x| x > 5).collect();
+        assert_eq!(selected.len(), 4);
+        assert!(selected.iter().all(|&(h, &x)| genvec.get_copy(h) == Some(x) && x > 5));
+    }
+
+    #[test]
+    fn alloc_contiguous_uses_consecutive_indices() {
+        let mut genvec = GenVec::new();
+        let a = genvec.alloc(0);
+        genvec.alloc(1);
+        genvec.free(a);
+        let block = genvec.alloc_contiguous([10, 11, 12, 13]).unwrap();
+        assert_eq!(block.map(|h| h.index()), [2, 3, 4, 5]);
+        assert_eq!(block.map(|h| genvec.get_copy(h).unwrap()), [10, 11, 12, 13]);
+        // The freelist was left alone
+        assert_eq!(genvec.alloc(9).index(), 0);
+    }
+
+    #[test]
+    fn genset_membership_and_removal() {
+        let mut set = GenSet::new();
+        let a = set.insert();
+        let b = set.insert();
+        assert!(set.contains(a) && set.contains(b));
+        assert!(set.remove(a));
+        assert!(!set.remove(a));
+        assert!(!set.contains(a));
+        let c = set.insert();
+        assert!(!set.contains(a) && set.contains(c));
+        assert_eq!(set.iter().collect::<Vec<_>>(), vec![c, b]);
+        assert_eq!(set.len(), 2);
+    }
+
+    #[test]
+    fn genset_remove_ignores_handle_from_bigger_set() {
+        let mut small = GenSet::new();
+        small.insert();
+        let mut big = GenSet::new();
+        let foreign: Vec<_> = (0..5).map(|_| big.insert()).collect();
+        assert!(!small.contains(foreign[4]));
+        assert!(!small.remove(foreign[4]));
+        assert_eq!(small.len(), 1);
+    }
+
+    #[test]
+    fn migrate_moves_one_element() {
+        let mut src = GenVec::new();
+        let mut dst = GenVec::new();
+        dst.alloc("x".to_string());
+        let h = src.alloc("moved".to_string());
+        let other = src.alloc("stays".to_string());
+        let new = src.migrate(h, &mut dst).unwrap();
+        assert!(!src.exists(h));
+        assert_eq!(dst.index_ref(new), "moved");
+        assert_eq!(src.index_ref(other), "stays");
+        assert!(src.migrate(h, &mut dst).is_none());
+        assert_eq!(dst.len(), 2);
+    }
+
+    /// Test-only corruption hook: make the cached live count drift
+    /// from the generations, as a path that forgot to update it would.
+    fn corrupt_live_count<T>(genvec: &mut GenVec<T>, delta: isize) {
+        genvec.live = genvec.live.checked_add_signed(delta).unwrap();
+    }
+
+    #[test]
+    fn cached_len_stays_in_sync() {
+        let mut genvec = GenVec::new();
+        let handles: Vec<_> = (0..20).map(|i| genvec.alloc(i)).collect();
+        for &h in handles.iter().step_by(3) {
+            genvec.free(h);
+        }
+        genvec.reserve_handle();
+        genvec.retain(|&x| x != 4);
+        genvec.compact();
+        genvec.alloc_contiguous([1, 2]);
+        let recount = genvec.generations.iter().filter(|&&generation| (generation & 1) == 0).count();
+        assert_eq!(genvec.len(), recount);
+        assert_eq!(genvec.iter().count(), recount);
+        assert_eq!(genvec.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn check_invariants_reports_len_drift() {
+        let mut genvec = GenVec::new();
+        genvec.alloc(1);
+        corrupt_live_count(&mut genvec, 1);
+        assert_eq!(genvec.check_invariants(), Err("Live count differs from len(): (1, 2)".to_string()));
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "Live count drifted from generations")]
+    fn iter_asserts_on_len_drift() {
+        let mut genvec = GenVec::new();
+        genvec.alloc(1);
+        genvec.alloc(2);
+        corrupt_live_count(&mut genvec, -1);
+        genvec.iter().count();
+    }
+
+    #[test]
+    fn handles_for_indices_mixed() {
+        let mut genvec = GenVec::new();
+        let handles: Vec<_> = (0..4).map(|i| genvec.alloc(i)).collect();
+        genvec.free(handles[1]);
+        assert_eq!(genvec.handles_for_indices(&[0, 1, 3, 9]), vec![Some(handles[0]), None, Some(handles[3]), None]);
+    }
+
+    #[test]
+    fn append_only_never_reuses_indices() {
+        let mut genvec = GenVec::append_only();
+        let handles: Vec<_> = (0..5).map(|i| genvec.alloc(i)).collect();
+        genvec.free(handles[1]);
+        genvec.free(handles[3]);
+        assert!(!genvec.exists(handles[1]));
+        let mut highest = 4;
+        for i in 0..5 {
+            let h = genvec.alloc(i);
+            assert!(h.index() > highest);
+            highest = h.index();
+            if i % 2 == 0 {
+                genvec.free(h);
+            }
+        }
+        assert!(genvec.freelist.is_empty());
+        assert_eq!(genvec.len(), 5);
+        assert_eq!(genvec.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn next_reuse_index_peeks_freelist() {
+        let mut genvec = GenVec::new();
+        let handles: Vec<_> = (0..5).map(|i| genvec.alloc(i)).collect();
+        assert_eq!(genvec.next_reuse_index(), None);
+        genvec.free(handles[3]);
+        assert_eq!(genvec.next_reuse_index(), Some(3));
+        assert_eq!(genvec.alloc(0).index(), 3);
+        assert_eq!(genvec.next_reuse_index(), None);
+    }
+
+    #[test]
+    fn for_handles_mut_skips_stale() {
+        let mut genvec = GenVec::new();
+        let handles: Vec<_> = (0..4).map(|i| genvec.alloc(i)).collect();
+        genvec.free(handles[2]);
+        genvec.for_handles_mut(&[handles[0], handles[2], handles[3]], |x| *x += 100);
+        assert_eq!(genvec.iter().copied().collect::<Vec<_>>(), vec![100, 1, 103]);
+    }
+
+    #[test]
+    fn are_disjoint_live_cases() {
+        let mut genvec = GenVec::new();
+        let handles: Vec<_> = (0..3).map(|i| genvec.alloc(i)).collect();
+        genvec.free(handles[2]);
+        assert!(genvec.are_disjoint_live(handles[0], handles[1]));
+        assert!(!genvec.are_disjoint_live(handles[0], handles[0]));
+        assert!(!genvec.are_disjoint_live(handles[0], handles[2]));
+        assert!(!genvec.are_disjoint_live(handles[2], handles[1]));
+    }
+
+    #[test]
+    fn from_slots_with_capacity_restores_capacity() {
+        let mut genvec = GenVec::with_capacity(500);
+        let a = genvec.alloc(1);
+        genvec.alloc(2);
+        genvec.free(a);
+        let slots: Vec<_> = genvec.raw_slots().map(|(_, generation, item)| item.map(|&x| (generation, x))).collect();
+        let loaded = GenVec::from_slots_with_capacity(slots, genvec.freelist.clone(), genvec.capacity()).unwrap();
+        assert!(loaded.capacity() >= 500);
+        // Never below the slot count, even with a smaller hint
+        let loaded = GenVec::from_slots_with_capacity(vec![Some((0, 1)), None, None], vec![2, 1], 0).unwrap();
+        assert!(loaded.capacity() >= 3);
+    }
+
+    #[test]
+    fn drain_older_than_splits_on_seq() {
+        let mut genvec = GenVec::new();
+        for i in 0..3 {
+            genvec.alloc(i);
+        }
+        let midpoint = genvec.next_seq();
+        let newer: Vec<_> = (3..6).map(|i| genvec.alloc(i)).collect();
+        assert_eq!(genvec.drain_older_than(midpoint), vec![0, 1, 2]);
+        assert!(newer.iter().all(|&h| genvec.exists(h)));
+        assert_eq!(genvec.len(), 3);
+    }
+
+    #[test]
+    fn handles_view_iterates_and_frees() {
+        let mut genvec = GenVec::new();
+        let handles: Vec<_> = (0..4).map(|i| genvec.alloc(i)).collect();
+        let mut seen = vec![];
+        for h in genvec.handles_view() {
+            seen.push(h);
+        }
+        assert_eq!(seen, handles);
+        let odd: Vec<_> = genvec.handles_view().into_iter().filter(|h| h.index() % 2 == 1).collect();
+        for h in odd {
+            genvec.free(h);
+        }
+        assert_eq!(genvec.len(), 2);
+    }
+
+    #[test]
+    fn resize_grows_freelist() {
+        let mut genvec = GenVec::new();
+        genvec.alloc(1);
+        genvec.resize(5).unwrap();
+        assert_eq!(genvec.free_count(), 4);
+        assert_eq!(genvec.alloc(2).index(), 1);
+        assert_eq!(genvec.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn resize_shrinks_only_free_tail() {
+        let mut genvec = GenVec::new();
+        let a = genvec.alloc(1);
+        genvec.resize(5).unwrap();
+        let b = genvec.alloc(2);
+        genvec.resize(2).unwrap();
+        assert_eq!(genvec.high_water_mark(), 2);
+        assert_eq!(genvec.free_count(), 0);
+        assert_eq!(genvec.resize(1).err(), Some(ResizeError { generation: 2, index: 1 }));
+        assert_eq!(genvec.high_water_mark(), 2);
+        assert!(genvec.exists(a) && genvec.exists(b));
+        assert_eq!(genvec.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn was_freed_flips_after_free() {
+        let mut genvec = GenVec::new();
+        genvec.set_free_audit(true);
+        let h = genvec.alloc(1);
+        assert!(!genvec.was_freed(h));
+        genvec.free(h);
+        assert!(genvec.was_freed(h));
+        assert!(!genvec.was_freed(EntryHandle::from_parts(0, 4)));
+        genvec.set_free_audit(false);
+        assert!(!genvec.was_freed(h));
+    }
+
+    #[test]
+    #[should_panic(expected = "Handle used after free")]
+    fn index_ref_panics_on_audited_free() {
+        let mut genvec = GenVec::new();
+        genvec.set_free_audit(true);
+        let h = genvec.alloc(1);
+        genvec.free(h);
+        genvec.index_ref(h);
+    }
+
+    #[test]
+    fn free_audit_accepts_handles_valid_again() {
+        let mut genvec = GenVec::new();
+        genvec.set_free_audit(true);
+        let h = genvec.alloc(String::from("a"));
+        let snapshot = genvec.snapshot();
+        genvec.free(h);
+        genvec.restore(snapshot);
+        assert!(!genvec.was_freed(h));
+        assert_eq!(genvec.index_ref(h), "a");
+        genvec.index_mut(h).push('b');
+        assert_eq!(genvec.index_ref(h), "ab");
+    }
+
+    #[test]
+    fn live_runs_mut_split_at_holes() {
+        let mut genvec = GenVec::new();
+        let handles: Vec<_> = (0..7).map(|i| genvec.alloc(i)).collect();
+        genvec.free(handles[3]);
+        let lengths: Vec<_> = genvec.live_runs_mut().map(|run| run.len()).collect();
+        assert_eq!(lengths, vec![3, 3]);
+        for run in genvec.live_runs_mut() {
+            for item in run {
+                *item *= 2;
+            }
+        }
+        assert_eq!(genvec.copied().collect::<Vec<_>>(), vec![0, 2, 4, 8, 10, 12]);
+    }
+
+    #[test]
+    fn reclaim_retired_returns_retired_slots() {
+        let mut genvec = GenVec::new();
+        genvec.set_generation_limit(Some((4, GenerationWrap::Retire)));
+        let keep = genvec.alloc(100);
+        let mut h = genvec.alloc(1);
+        genvec.free(h);
+        h = genvec.alloc(2);
+        genvec.free(h);
+        assert_eq!(genvec.retired_count(), 1);
+        assert_eq!(genvec.alloc(3).index(), 2);
+        let mut remap = HashMap::new();
+        assert_eq!(genvec.reclaim_retired(|old, new| { remap.insert(old, new); }), 1);
+        assert_eq!(genvec.retired_count(), 0);
+        assert_eq!(genvec.get_copy(remap[&keep]), Some(100));
+        assert!(!genvec.exists(keep) && !genvec.exists(h));
+        assert_eq!(genvec.alloc(4).index(), 1);
+        assert_eq!(genvec.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn reclaimed_slots_stay_reusable_through_compaction() {
+        let mut genvec = GenVec::new();
+        genvec.set_generation_limit(Some((4, GenerationWrap::Retire)));
+        let mut h = genvec.alloc(1);
+        genvec.free(h);
+        h = genvec.alloc(2);
+        genvec.free(h);
+        genvec.alloc(3);
+        assert_eq!(genvec.reclaim_retired(|_, _| {}), 1);
+        assert!(!genvec.is_retired(0));
+        genvec.compact();
+        assert_eq!(genvec.len(), 1);
+        assert_eq!(genvec.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid generation limit: 2")]
+    fn generation_limit_below_four_panics() {
+        let mut genvec: GenVec<u8> = GenVec::new();
+        genvec.set_generation_limit(Some((2, GenerationWrap::Retire)));
+    }
+
+    #[test]
+    fn from_sparse_fills_gaps_with_free_slots() {
+        let genvec = GenVec::from_sparse([(0, 'a'), (3, 'd')]);
+        let handles = genvec.handles_for_indices(&[0, 1, 2, 3]);
+        assert!(handles[0].is_some() && handles[3].is_some());
+        assert!(handles[1].is_none() && handles[2].is_none());
+        assert_eq!((genvec.len(), genvec.free_count()), (2, 2));
+        assert_eq!(genvec.check_invariants(), Ok(()));
+        // Last value wins on a repeated index
+        let genvec = GenVec::from_sparse([(1, 'a'), (1, 'b')]);
+        assert_eq!(genvec.iter().copied().collect::<Vec<_>>(), vec!['b']);
+    }
+
+    #[test]
+    fn deep_memory_usage_adds_heap_bytes() {
+        let mut genvec: GenVec<String> = GenVec::new();
+        genvec.alloc("hello".to_string());
+        let gone = genvec.alloc("not counted".to_string());
+        genvec.alloc("world!!".to_string());
+        genvec.free(gone);
+        let flat = genvec.deep_memory_usage(|_| 0);
+        assert!(flat >= 3 * size_of::<String>());
+        assert_eq!(genvec.deep_memory_usage(|s| s.len()), flat + 12);
+    }
+
+    #[test]
+    fn swap_with_moves_handles_with_data() {
+        let mut a = GenVec::new();
+        let mut b = GenVec::new();
+        let in_a = a.alloc(1);
+        b.alloc(9);
+        let in_b = b.alloc(2);
+        a.swap_with(&mut b);
+        assert_eq!(b.get_ref(in_a), Some(&1));
+        assert_eq!(a.get_ref(in_b), Some(&2));
+        assert_eq!((a.len(), b.len()), (2, 1));
+    }
+
+    #[test]
+    fn group_by_key_buckets_by_parity() {
+        let mut genvec = GenVec::new();
+        let handles: Vec<_> = (0..5).map(|i| genvec.alloc(i)).collect();
+        let groups = genvec.group_by_key(|x| x % 2);
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[&0].iter().map(|&(h, _)| h).collect::<Vec<_>>(), vec![handles[0], handles[2], handles[4]]);
+        assert_eq!(groups[&1].iter().map(|&(h, &x)| (h, x)).collect::<Vec<_>>(), vec![(handles[1], 1), (handles[3], 3)]);
+    }
+
+    #[test]
+    fn free_where_flagged_frees_matching() {
+        let mut genvec = GenVec::new();
+        let handles: Vec<_> = (0..5).map(|i| genvec.alloc(i)).collect();
+        // Stand-in for a SecondaryMap of flags
+        let flags = HashMap::from([(handles[1], true), (handles[3], true), (handles[4], false)]);
+        assert_eq!(genvec.free_where_flagged(&flags, |&dead| dead), 2);
+        assert!(!genvec.exists(handles[1]) && !genvec.exists(handles[3]));
+        assert!(genvec.exists(handles[0]) && genvec.exists(handles[2]) && genvec.exists(handles[4]));
+        assert_eq!(genvec.free_where_flagged(&flags, |&dead| dead), 0);
+    }
+
+    #[test]
+    fn with_uninit_then_assume_all_init() {
+        let mut genvec: GenVec<String> = GenVec::with_uninit(4);
+        assert_eq!(genvec.len(), 0);
+        for i in 0..4 {
+            genvec.init_slot(i, i.to_string());
+        }
+        // SAFETY: init_slot was called for every slot above
+        unsafe { genvec.assume_all_init() };
+        assert_eq!(genvec.len(), 4);
+        assert_eq!(genvec.iter().cloned().collect::<Vec<_>>(), vec!["0", "1", "2", "3"]);
+        assert_eq!(genvec.check_invariants(), Ok(()));
+        assert_eq!(genvec.alloc("4".to_string()).index(), 4);
+    }
+
+    #[test]
+    fn for_each_mut_with_adds_context() {
+        let mut genvec = GenVec::new();
+        let first = genvec.alloc(1);
+        genvec.alloc(2);
+        genvec.free(first);
+        genvec.alloc(3);
+        genvec.for_each_mut_with(&10, |offset, x| *x += *offset);
+        let mut values: Vec<_> = genvec.iter().copied().collect();
+        values.sort();
+        assert_eq!(values, vec![12, 13]);
+    }
+
+    #[test]
+    fn cancel_reservation_rolls_back_unfilled() {
+        let mut genvec = GenVec::new();
+        let filled = genvec.reserve_handle();
+        let cancelled = genvec.reserve_handle();
+        assert!(genvec.fill(filled, 1).is_ok());
+        assert!(genvec.cancel_reservation(cancelled));
+        assert!(!genvec.cancel_reservation(cancelled));
+        assert!(!genvec.cancel_reservation(filled));
+        assert_eq!(genvec.get_ref(filled), Some(&1));
+        assert_eq!(genvec.get_ref(cancelled), None);
+        assert!(genvec.fill(cancelled, 2).is_err());
+        assert_eq!(genvec.check_invariants(), Ok(()));
+        let reused = genvec.alloc(3);
+        assert_eq!(reused.index(), cancelled.index());
+        assert_eq!(genvec.get_ref(cancelled), None);
+    }
+
+    #[test]
+    fn handle_bytes_roundtrip() {
+        let mut genvec = GenVec::new();
+        let h = genvec.alloc(5);
+        genvec.free(h);
+        let h = genvec.alloc(6);
+        let mut buf = [0u8; 16];
+        h.write_bytes(&mut buf);
+        assert_eq!(EntryHandle::<i32>::read_bytes(&buf), h);
+        assert_eq!(genvec.get_ref(EntryHandle::read_bytes(&buf)), Some(&6));
+    }
+
+    #[test]
+    fn handle_bytes_layout() {
+        let h: EntryHandle<u8> = EntryHandle::from_parts(0x0102, 0x0a0b);
+        let mut buf = [0u8; 16];
+        h.write_bytes(&mut buf);
+        assert_eq!(buf, [0x0b, 0x0a, 0, 0, 0, 0, 0, 0, 0x02, 0x01, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn live_peekable_peeks_without_consuming() {
+        let mut genvec = GenVec::new();
+        let a = genvec.alloc('a');
+        let b = genvec.alloc('b');
+        let mut live = genvec.live_peekable();
+        assert_eq!(live.peek(), Some(&(a, &'a')));
+        assert_eq!(live.next(), Some((a, &'a')));
+        assert_eq!(live.peek().map(|&(h, _)| h), Some(b));
+        assert_eq!(live.next(), Some((b, &'b')));
+        assert!(live.peek().is_none());
+    }
+
+    #[test]
+    fn checksum_matches_identical_arenas() {
+        let mut a = GenVec::new();
+        let mut b = GenVec::new();
+        for i in 0..5 {
+            a.alloc(i);
+            b.alloc(i);
+        }
+        assert_eq!(a.checksum(), b.checksum());
+        let h = b.handle_at(2).unwrap();
+        *b.get_mut(h).unwrap() = 99;
+        assert_ne!(a.checksum(), b.checksum());
+        *b.get_mut(h).unwrap() = 2;
+        assert_eq!(a.checksum(), b.checksum());
+        // Same value back in the slot, but under a newer generation
+        b.free(h);
+        b.alloc(2);
+        assert_ne!(a.checksum(), b.checksum());
+    }
+
+    #[test]
+    fn hole_runs_reports_maximal_runs() {
+        let mut genvec = GenVec::new();
+        let handles: Vec<_> = (0..7).map(|i| genvec.alloc(i)).collect();
+        for i in [1, 2, 5] {
+            genvec.free(handles[i]);
+        }
+        assert_eq!(genvec.hole_runs().collect::<Vec<_>>(), vec![(1, 2), (5, 1)]);
+        genvec.free(handles[6]);
+        assert_eq!(genvec.hole_runs().collect::<Vec<_>>(), vec![(1, 2), (5, 2)]);
+        assert_eq!(GenVec::<u8>::new().hole_runs().count(), 0);
+    }
+
+    #[test]
+    fn max_contiguous_free_finds_longest_hole() {
+        let mut genvec = GenVec::new();
+        assert_eq!(genvec.max_contiguous_free(), 0);
+        let handles: Vec<_> = (0..8).map(|i| genvec.alloc(i)).collect();
+        assert_eq!(genvec.max_contiguous_free(), 0);
+        for i in [1, 3, 4, 5, 7] {
+            genvec.free(handles[i]);
+        }
+        assert_eq!(genvec.max_contiguous_free(), 3);
+        genvec.ensure_index(4);
+        assert_eq!(genvec.max_contiguous_free(), 1);
+    }
+
+    #[test]
+    fn resume_iter_mut_continues_from_saved_handle() {
+        let mut genvec = GenVec::new();
+        let handles: Vec<_> = (0..6).map(|i| genvec.alloc(i)).collect();
+        genvec.free(handles[4]);
+        let mut saved = None;
+        for (n, (h, x)) in genvec.resume_iter_mut(None).enumerate() {
+            if n == 2 {
+                saved = Some(h);
+                break;
+            }
+            *x += 100;
+        }
+        assert_eq!(saved, Some(handles[2]));
+        for (_, x) in genvec.resume_iter_mut(saved) {
+            *x += 100;
+        }
+        assert_eq!(genvec.iter().copied().collect::<Vec<_>>(), vec![100, 101, 102, 103, 105]);
+    }
+
+    #[test]
+    fn net_id_map_with_custom_hasher() {
+        use std::hash::BuildHasherDefault;
+        let mut genvec = GenVec::new();
+        let mut ids: NetIdMap<u32, BuildHasherDefault<DefaultHasher>> = NetIdMap::default();
+        let (h, id) = ids.alloc(&mut genvec, 7);
+        assert_eq!(ids.handle(id), Some(h));
+        assert_eq!(ids.net_id(h), Some(id));
+        assert_eq!(ids.free(&mut genvec, h), Some(id));
+        assert!(ids.is_empty());
+        assert!(!genvec.exists(h));
+    }
+
+    #[test]
+    fn take_where_returns_owned_matches() {
+        let mut genvec = GenVec::new();
+        let handles: Vec<_> = (0..6).map(|i| genvec.alloc(i)).collect();
+        assert_eq!(genvec.take_where(|x| x % 2 == 0), vec![0, 2, 4]);
+        assert_eq!(genvec.iter().copied().collect::<Vec<_>>(), vec![1, 3, 5]);
+        assert!(!genvec.exists(handles[0]) && genvec.exists(handles[1]));
+        assert_eq!(genvec.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn total_allocations_counts_lifetime_allocs() {
+        let mut genvec = GenVec::new();
+        let handles: Vec<_> = (0..5).map(|i| genvec.alloc(i)).collect();
+        genvec.free(handles[0]);
+        genvec.free(handles[3]);
+        genvec.alloc(5);
+        genvec.alloc(6);
+        assert_eq!(genvec.total_allocations(), 7);
+        assert_eq!(genvec.len(), 5);
+        genvec.free(handles[1]);
+        genvec.compact();
+        assert_eq!(genvec.total_allocations(), 7);
+    }
+
+    #[test]
+    fn shuffled_handles_is_seeded() {
+        let mut genvec = GenVec::new();
+        for i in 0..50 {
+            genvec.alloc(i);
+        }
+        let order = genvec.shuffled_handles(1);
+        assert_eq!(order, genvec.shuffled_handles(1));
+        assert_ne!(order, genvec.shuffled_handles(2));
+        assert_ne!(genvec.shuffled_handles(0), genvec.shuffled_handles(1));
+        let mut sorted = order.clone();
+        sorted.sort();
+        assert_eq!(sorted, genvec.iter_with_handles().map(|(h, _)| h).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn reserve_freelist_absorbs_free_burst() {
+        let mut genvec = GenVec::new();
+        let handles: Vec<_> = (0..100).map(|i| genvec.alloc(i)).collect();
+        genvec.reserve_freelist(100);
+        let reserved = genvec.freelist.capacity();
+        assert!(reserved >= 100);
+        for h in handles {
+            genvec.free(h);
+        }
+        assert_eq!(genvec.freelist.capacity(), reserved);
+    }
+
+    #[test]
+    fn index_of_only_for_valid_handles() {
+        let mut genvec = GenVec::new();
+        let a = genvec.alloc(1);
+        let b = genvec.alloc(2);
+        assert_eq!(genvec.index_of(b), Some(1));
+        genvec.free(a);
+        assert_eq!(genvec.index_of(a), None);
+        assert_eq!(genvec.index_of(EntryHandle::from_parts(9, 0)), None);
+    }
+
+    #[test]
+    fn recycle_invalidates_old_handle() {
+        let mut genvec = GenVec::new();
+        genvec.alloc(0);
+        let old = genvec.alloc(1);
+        // A free slot ahead of old's on the freelist mustn't be taken instead
+        let other = genvec.alloc(9);
+        genvec.free(other);
+        let new = genvec.recycle(old, 2).unwrap();
+        assert_eq!(new.index(), old.index());
+        assert_eq!(genvec.get_ref(old), None);
+        assert_eq!(genvec.get_ref(new), Some(&2));
+        assert_eq!(genvec.recycle(old, 3), None);
+        assert_eq!(genvec.len(), 2);
+    }
+
+    #[test]
+    fn count_in_range_skips_holes() {
+        let mut genvec = GenVec::new();
+        let handles: Vec<_> = (0..10).map(|i| genvec.alloc(i)).collect();
+        genvec.free(handles[4]);
+        assert_eq!(genvec.count_in_range(2..7), 4);
+        assert_eq!(genvec.count_in_range(0..100), 9);
+        assert_eq!(genvec.count_in_range(20..30), 0);
+    }
+
+    #[test]
+    fn enumerate_live_skips_holes() {
+        let mut genvec = GenVec::new();
+        let handles: Vec<_> = (0..4).map(|i| genvec.alloc(i * 10)).collect();
+        genvec.free(handles[1]);
+        assert_eq!(genvec.enumerate_live().collect::<Vec<_>>(), vec![(0, &0), (2, &20), (3, &30)]);
+    }
+
+    #[test]
+    fn apply_patch_reconciles_arenas() {
+        let live = |genvec: &GenVec<i32>| genvec.iter_with_handles().map(|(h, &x)| (h, x)).collect::<Vec<_>>();
+        let mut source = GenVec::new();
+        let handles: Vec<_> = (0..5).map(|i| source.alloc(i)).collect();
+        let mut target = source.clone();
+        target.free(handles[1]);
+        target.free(handles[2]);
+        target.alloc(20);
+        *target.get_mut(handles[3]).unwrap() = 33;
+        target.alloc(50);
+        target.alloc(60);
+        let patch = source.diff_patch(&target);
+        assert_eq!(patch.removed.len(), 2);
+        assert_eq!(patch.added.len(), 3);
+        assert_eq!(patch.changed, vec![(handles[3], 33)]);
+        let mut replica = source.clone();
+        replica.apply_patch(patch);
+        assert_eq!(live(&replica), live(&target));
+        assert_eq!(replica.check_invariants(), Ok(()));
+        assert_eq!(replica.diff_patch(&target), Patch { removed: vec![], added: vec![], changed: vec![] });
+        // Patching an empty arena builds the target from scratch
+        let mut fresh: GenVec<i32> = GenVec::new();
+        fresh.apply_patch(GenVec::new().diff_patch(&target));
+        assert_eq!(live(&fresh), live(&target));
+        assert_eq!(fresh.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn process_retain_mutates_then_frees() {
+        let mut genvec = GenVec::new();
+        let handles: Vec<_> = (0..5).map(|i| genvec.alloc(i)).collect();
+        let mut seen = Vec::new();
+        genvec.process_retain(|h, x| {
+            seen.push(h);
+            *x *= 10;
+            return *x < 25;
+        });
+        assert_eq!(seen, handles);
+        assert_eq!(genvec.iter().copied().collect::<Vec<_>>(), vec![0, 10, 20]);
+        assert!(!genvec.exists(handles[3]) && !genvec.exists(handles[4]));
+    }
+
+    #[test]
+    fn spare_capacity_tracks_push_headroom() {
+        let mut genvec = GenVec::with_capacity(4);
+        let start = genvec.spare_capacity();
+        assert!(start >= 4);
+        let h = genvec.alloc(1);
+        assert_eq!(genvec.spare_capacity(), start - 1);
+        // Reusing a freed slot doesn't push
+        genvec.free(h);
+        genvec.alloc(2);
+        assert_eq!(genvec.spare_capacity(), start - 1);
+        genvec.reserve(100);
+        assert!(genvec.spare_capacity() >= 100);
+    }
+
+    #[test]
+    fn into_handle_map_keys_by_handle() {
+        let mut genvec = GenVec::new();
+        let handles: Vec<_> = (0..4).map(|i| genvec.alloc(i.to_string())).collect();
+        genvec.free(handles[1]);
+        let map = genvec.into_handle_map();
+        assert_eq!(map.len(), 3);
+        assert_eq!(map[&handles[0]], "0");
+        assert_eq!(map[&handles[3]], "3");
+        assert!(!map.contains_key(&handles[1]));
+    }
+
+    #[test]
+    fn handle_map_roundtrip_preserves_handles() {
+        let mut genvec = GenVec::new();
+        let handles: Vec<_> = (0..5).map(|i| genvec.alloc(i)).collect();
+        genvec.free(handles[1]);
+        genvec.free(handles[2]);
+        let reused = genvec.alloc(22);
+        let live: Vec<_> = genvec.iter_with_handles().map(|(h, &x)| (h, x)).collect();
+        let rebuilt = GenVec::from_handle_map(genvec.into_handle_map()).unwrap();
+        assert_eq!(rebuilt.iter_with_handles().map(|(h, &x)| (h, x)).collect::<Vec<_>>(), live);
+        assert_eq!(rebuilt.get_ref(reused), Some(&22));
+        assert!(!rebuilt.exists(handles[1]) && !rebuilt.exists(handles[2]));
+        assert_eq!(rebuilt.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn from_handle_map_rejects_inconsistent_keys() {
+        let conflict = HashMap::from([(EntryHandle::from_parts(0, 0), 1), (EntryHandle::from_parts(0, 2), 2)]);
+        assert!(matches!(GenVec::from_handle_map(conflict), Err(RebuildError::Conflict { index: 0, .. })));
+        let odd = HashMap::from([(EntryHandle::from_parts(3, 1), 1)]);
+        assert_eq!(GenVec::from_handle_map(odd).unwrap_err(), RebuildError::OddGeneration { generation: 1, index: 3 });
+    }
+
+    #[test]
+    fn to_packed_maps_handles_to_positions() {
+        let mut genvec = GenVec::new();
+        let handles: Vec<_> = (0..6).map(|i| genvec.alloc(i * 3)).collect();
+        genvec.free(handles[0]);
+        genvec.free(handles[4]);
+        let (values, positions) = genvec.to_packed();
+        assert_eq!(values.len(), genvec.len());
+        for (h, x) in genvec.iter_with_handles() {
+            assert_eq!(values[positions[&h]], *x);
+        }
+        assert!(!positions.contains_key(&handles[0]));
+    }
+
+    #[test]
+    fn iter_compacting_mut_fills_holes() {
+        let mut genvec = GenVec::new();
+        let handles: Vec<_> = (0..6).map(|i| genvec.alloc(i)).collect();
+        genvec.free(handles[1]);
+        genvec.free(handles[3]);
+        let mut walk = genvec.iter_compacting_mut();
+        let mut seen = Vec::new();
+        while let Some((h, x)) = walk.next() {
+            *x += 10;
+            seen.push((h.index(), *x));
+        }
+        let remap = walk.finish();
+        assert_eq!(seen, vec![(0, 10), (1, 12), (2, 14), (3, 15)]);
+        assert_eq!(remap.len(), 3);
+        for (old, new) in &remap {
+            assert!(!genvec.exists(*old));
+            assert!(genvec.exists(*new));
+        }
+        assert_eq!(genvec.fragmentation(), 0.0);
+        assert_eq!(genvec.check_invariants(), Ok(()));
+        assert_eq!(genvec.alloc(99).index(), 4);
+    }
+
+    #[test]
+    fn iter_compacting_mut_finishes_on_drop() {
+        let mut genvec = GenVec::new();
+        let handles: Vec<_> = (0..6).map(|i| genvec.alloc(i)).collect();
+        genvec.free(handles[0]);
+        {
+            let mut walk = genvec.iter_compacting_mut();
+            walk.next();
+        }
+        assert_eq!(genvec.fragmentation(), 0.0);
+        assert_eq!(genvec.check_invariants(), Ok(()));
+        assert_eq!(genvec.iter().copied().collect::<Vec<_>>(), vec![1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn no_generation_collision_on_valid_arena() {
+        let mut genvec = GenVec::new();
+        let h = genvec.alloc(1);
+        genvec.alloc(2);
+        genvec.free(h);
+        genvec.reserve_handle();
+        genvec.assert_no_generation_collision();
+    }
+
+    #[test]
+    #[should_panic(expected = "Live slot marked free")]
+    fn no_generation_collision_panics_on_live_freelist_slot() {
+        // from_slots refuses this freelist, so corrupt one after the fact
+        assert_eq!(GenVec::from_slots(vec![Some((0, 'a')), None], vec![1, 0]).unwrap_err(), FromSlotsError::LiveOnFreelist { generation: 0, index: 0 });
+        let mut genvec = GenVec::from_slots(vec![Some((0, 'a')), None], vec![1]).unwrap();
+        genvec.assert_no_generation_collision();
+        genvec.freelist.push(0);
+        genvec.assert_no_generation_collision();
+    }
+
+    #[test]
+    fn par_alloc_with_fills_in_order() {
+        let mut genvec = GenVec::new();
+        let first = genvec.alloc(usize::MAX);
+        genvec.free(first);
+        let handles = genvec.par_alloc_with(10_000, |i| i * 2);
+        assert_eq!(handles.len(), 10_000);
+        for (i, &h) in handles.iter().enumerate() {
+            assert_eq!(genvec.get_ref(h), Some(&(i * 2)));
+            assert_eq!(h.index(), i + 1);
+        }
+        assert_eq!(genvec.len(), 10_000);
+        assert_eq!(genvec.check_invariants(), Ok(()));
+        assert!(genvec.par_alloc_with(0, |i| i).is_empty());
+        let mut strings: GenVec<String> = GenVec::new();
+        strings.par_alloc_with(7, |i| i.to_string());
+        assert_eq!(strings.iter().cloned().collect::<Vec<_>>(), ["0", "1", "2", "3", "4", "5", "6"]);
+    }
+
+    #[test]
+    fn get_parallel_requires_matching_slots() {
+        let mut numbers = GenVec::new();
+        let mut names = GenVec::new();
+        let number = numbers.alloc(1);
+        let name = names.alloc("one");
+        assert_eq!(get_parallel(&numbers, &names, number.index(), number.generation()), Some((&1, &"one")));
+        numbers.free(number);
+        let renumbered = numbers.alloc(2);
+        assert_eq!(get_parallel(&numbers, &names, renumbered.index(), renumbered.generation()), None);
+        assert_eq!(get_parallel(&numbers, &names, name.index(), name.generation()), None);
+        assert_eq!(get_parallel(&numbers, &names, 5, 0), None);
+    }
+
+    #[test]
+    fn refill_reuses_capacity() {
+        let mut genvec = GenVec::with_capacity(8);
+        let old: Vec<_> = (0..8).map(|i| genvec.alloc(i)).collect();
+        let capacity = genvec.capacity();
+        genvec.refill([10, 11, 12]);
+        assert_eq!(genvec.capacity(), capacity);
+        assert_eq!(genvec.iter_with_handles().map(|(h, _)| h.index()).collect::<Vec<_>>(), vec![0, 1, 2]);
+        assert_eq!(genvec.iter().copied().collect::<Vec<_>>(), vec![10, 11, 12]);
+        assert!(old.iter().all(|&h| !genvec.exists(h)));
+        assert_eq!(genvec.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn age_counts_later_allocs() {
+        let mut genvec = GenVec::new();
+        let h = genvec.alloc(0);
+        assert_eq!(genvec.age(h), Some(0));
+        for i in 0..3 {
+            genvec.alloc(i);
+        }
+        assert_eq!(genvec.age(h), Some(3));
+        genvec.free(h);
+        assert_eq!(genvec.age(h), None);
+    }
+
+    #[test]
+    fn cloned_values_reserves_exactly() {
+        let mut genvec = GenVec::new();
+        let handles: Vec<_> = (0..5).map(|i| genvec.alloc(i.to_string())).collect();
+        genvec.free(handles[2]);
+        let values = genvec.cloned_values();
+        assert_eq!(values, genvec.iter().cloned().collect::<Vec<_>>());
+        assert_eq!(values.len(), 4);
+        assert_eq!(values.capacity(), 4);
+    }
+
+    #[test]
+    fn component_handle_routes_to_arena() {
+        use std::any::Any;
+        let mut registry: Vec<Box<dyn Any>> = vec![Box::new(GenVec::<u32>::new()), Box::new(GenVec::<String>::new())];
+        let h = registry[1].downcast_mut::<GenVec<String>>().unwrap().alloc("pos".to_string());
+        let component = h.with_type_id(TypeId::of::<String>());
+        assert_eq!(component.type_id(), TypeId::of::<String>());
+        assert!(component.try_into_handle::<u32>().is_none());
+        let typed = component.try_into_handle::<String>().unwrap();
+        assert_eq!(typed, h);
+        let arena = registry.iter().find_map(|arena| arena.downcast_ref::<GenVec<String>>()).unwrap();
+        assert_eq!(arena.get_ref(typed).map(String::as_str), Some("pos"));
+    }
+
+    #[test]
+    fn reserve_pow2_rounds_up() {
+        let mut genvec: GenVec<u64> = GenVec::new();
+        genvec.alloc(1);
+        genvec.reserve_pow2(100);
+        assert!(genvec.capacity() >= 128);
+        assert!(genvec.capacity().is_power_of_two());
+    }
+
+    #[test]
+    fn iterate_budget_visits_each_once() {
+        let mut genvec = GenVec::new();
+        for _ in 0..10 {
+            genvec.alloc(0);
+        }
+        let mut resume = None;
+        let mut calls = 0;
+        loop {
+            genvec.iterate_budget(3, |x| *x += 1, &mut resume);
+            calls += 1;
+            if resume.is_none() {
+                break;
+            }
+        }
+        assert_eq!(calls, 4);
+        assert!(genvec.iter().all(|&x| x == 1));
+        // A zero budget leaves the token on the first element
+        let mut resume = None;
+        genvec.iterate_budget(0, |x| *x += 1, &mut resume);
+        assert_eq!(resume, genvec.handle_at(0));
+    }
+
+    #[test]
+    fn validate_raw_keeps_only_live_handles() {
+        let mut genvec = GenVec::new();
+        let a = genvec.alloc(1);
+        let stale = genvec.alloc(2);
+        genvec.free(stale);
+        let c = genvec.alloc(3);
+        let far: EntryHandle<i32> = EntryHandle::from_parts(1000, 0);
+        let raws = [a.to_slotmap_keydata(), stale.to_slotmap_keydata(), far.to_slotmap_keydata(), 0, c.to_slotmap_keydata()];
+        assert_eq!(genvec.validate_raw(&raws), vec![Some(a), None, None, None, Some(c)]);
+    }
+
+    #[test]
+    fn merge_by_key_allocates_in_key_order() {
+        let mut a = GenVec::new();
+        let mut b = GenVec::new();
+        for x in [5, 1, 9, 3] {
+            a.alloc(x);
+        }
+        for x in [4, 3, 10] {
+            b.alloc(x);
+        }
+        let merged = a.merge_by_key(b, |x| *x);
+        assert_eq!(merged.iter().copied().collect::<Vec<_>>(), vec![1, 3, 3, 4, 5, 9, 10]);
+        assert_eq!(merged.len(), 7);
+        let descending = GenVec::new().merge_by_key(merged, |x: &i32| -x);
+        assert_eq!(descending.iter().copied().collect::<Vec<_>>(), vec![10, 9, 5, 4, 3, 3, 1]);
+    }
+
+    #[test]
+    fn borrow_mut_guard_persists_writes() {
+        let mut genvec = GenVec::new();
+        let h = genvec.alloc(vec![1]);
+        {
+            let mut guard = genvec.borrow_mut(h).unwrap();
+            guard.push(2);
+            assert_eq!(guard.handle(), h);
+            assert_eq!(guard.len(), 2);
+        }
+        assert_eq!(genvec.get_ref(h), Some(&vec![1, 2]));
+        genvec.free(h);
+        assert!(genvec.borrow_mut(h).is_none());
+        assert!(genvec.borrow_mut(EntryHandle::from_parts(7, 0)).is_none());
+    }
+
+    #[test]
+    fn ui_rows_number_live_elements() {
+        let mut genvec = GenVec::new();
+        let handles: Vec<_> = (0..5).map(|i| genvec.alloc(i)).collect();
+        genvec.free(handles[1]);
+        genvec.free(handles[3]);
+        let rows: Vec<_> = genvec.ui_rows().map(|(h, row, &x)| (h.index(), row, x)).collect();
+        assert_eq!(rows, vec![(0, 0, 0), (2, 1, 2), (4, 2, 4)]);
+    }
+
+    #[test]
+    fn pin_capacity_keeps_storage_in_place() {
+        let mut genvec = GenVec::new();
+        genvec.pin_capacity(8);
+        let first = genvec.try_alloc(0).unwrap();
+        let ptr = genvec.get_ref(first).unwrap() as *const i32;
+        let mut handles = vec![first];
+        for i in 1..8 {
+            handles.push(genvec.try_alloc(i).unwrap());
+        }
+        assert_eq!(genvec.get_ref(first).unwrap() as *const i32, ptr);
+        assert!(genvec.try_alloc(8).is_none());
+        genvec.free(handles[3]);
+        assert!(genvec.try_alloc(9).is_some());
+        assert!(genvec.try_alloc(10).is_none());
+        assert_eq!(genvec.get_ref(first).unwrap() as *const i32, ptr);
+        genvec.unpin_capacity();
+        assert!(genvec.try_alloc(11).is_some());
+    }
+
+    #[test]
+    #[should_panic(expected = "Capacity pinned")]
+    fn pin_capacity_alloc_past_pin_panics() {
+        let mut genvec = GenVec::new();
+        genvec.pin_capacity(1);
+        genvec.alloc(1);
+        genvec.alloc(2);
+    }
+
+    #[test]
+    fn pin_capacity_through_restore_and_swap_with() {
+        let mut genvec = GenVec::new();
+        genvec.alloc(1);
+        let snapshot = genvec.snapshot();
+        genvec.pin_capacity(2);
+        genvec.alloc(2);
+        // restore replaces the storage, then reserves the pin again
+        genvec.restore(snapshot);
+        let ptr = genvec.data.as_ptr();
+        assert!(genvec.try_alloc(3).is_some());
+        assert_eq!(genvec.data.as_ptr(), ptr);
+        assert!(genvec.try_alloc(4).is_none());
+        // swap_with hands the pin over with the storage
+        let mut other = GenVec::new();
+        genvec.swap_with(&mut other);
+        assert!(other.try_alloc(5).is_none());
+        assert!(genvec.try_alloc(6).is_some() && genvec.try_alloc(7).is_some());
+    }
+
+    #[test]
+    fn pin_capacity_survives_compact_and_shrink() {
+        let mut genvec = GenVec::new();
+        genvec.pin_capacity(8);
+        let handles: Vec<_> = (0..4).map(|i| genvec.alloc(i)).collect();
+        genvec.free(handles[1]);
+        genvec.free(handles[3]);
+        genvec.compact_and_shrink();
+        assert!(genvec.capacity() >= 8);
+        let ptr = genvec.data.as_ptr();
+        for i in 0..6 {
+            assert!(genvec.try_alloc(i).is_some());
+        }
+        assert_eq!(genvec.data.as_ptr(), ptr);
+        assert!(genvec.try_alloc(6).is_none());
+    }
+
+    #[test]
+    fn fold_with_handles_finds_max_handle() {
+        let mut genvec = GenVec::new();
+        for x in [3, 9, 2, 7] {
+            genvec.alloc(x);
+        }
+        let best = genvec.fold_with_handles(None, |best: Option<(EntryHandle<i32>, i32)>, h, &x| match best {
+            Some((_, max)) if max >= x => best,
+            _ => Some((h, x)),
+        });
+        let (h, max) = best.unwrap();
+        assert_eq!(max, 9);
+        assert_eq!(genvec.get_ref(h), Some(&9));
+        assert_eq!(genvec.fold_with_handles(0, |n, _, _| n + 1), 4);
+    }
+
+    #[test]
+    fn disable_hides_from_iter_only() {
+        let mut genvec = GenVec::new();
+        let handles: Vec<_> = (0..5).map(|i| genvec.alloc(i)).collect();
+        assert!(genvec.disable(handles[1]));
+        assert!(!genvec.disable(handles[1]));
+        assert!(genvec.is_disabled(handles[1]));
+        assert_eq!(genvec.iter().copied().collect::<Vec<_>>(), vec![0, 2, 3, 4]);
+        for x in genvec.iter_mut() {
+            *x += 10;
+        }
+        assert_eq!(genvec.get_ref(handles[1]), Some(&1));
+        assert_eq!(genvec.len(), 5);
+        assert_eq!(genvec.check_invariants(), Ok(()));
+        assert!(genvec.enable(handles[1]));
+        assert!(!genvec.enable(handles[1]));
+        assert_eq!(genvec.iter().copied().collect::<Vec<_>>(), vec![10, 1, 12, 13, 14]);
+    }
+
+    #[test]
+    fn disable_flag_follows_compaction_and_dies_with_slot() {
+        let mut genvec = GenVec::new();
+        let handles: Vec<_> = (0..5).map(|i| genvec.alloc(i)).collect();
+        genvec.disable(handles[3]);
+        genvec.free(handles[0]);
+        let remap = genvec.compact();
+        let moved = remap.iter().find(|&&(old, _)| old == handles[3]).unwrap().1;
+        assert!(genvec.is_disabled(moved));
+        assert_eq!(genvec.iter().copied().collect::<Vec<_>>(), vec![1, 2, 4]);
+        assert_eq!(genvec.check_invariants(), Ok(()));
+        genvec.free(moved);
+        let reused = genvec.alloc(99);
+        assert!(!genvec.is_disabled(reused));
+        assert!(genvec.iter().any(|&x| x == 99));
+        assert_eq!(genvec.check_invariants(), Ok(()));
+        assert_eq!(genvec.clone().iter().count(), genvec.iter().count());
+    }
+
+    #[test]
+    fn disabled_elements_still_counted() {
+        let mut genvec = GenVec::new();
+        let handles: Vec<_> = (0..4).map(|i| genvec.alloc(i.to_string())).collect();
+        let usage = genvec.deep_memory_usage(|_| 100);
+        genvec.disable(handles[2]);
+        assert_eq!(genvec.deep_memory_usage(|_| 100), usage);
+        assert_eq!(genvec.cloned_values(), vec!["0", "1", "2", "3"]);
+        assert_eq!(genvec.cloned_values().len(), genvec.len());
+        assert_eq!(genvec.count_where(|x| x == "2"), 1);
+    }
+
+    #[test]
+    fn disabled_elements_hidden_only_from_iter() {
+        let mut genvec = GenVec::new();
+        let handles: Vec<_> = (0..4).map(|i| genvec.alloc(i)).collect();
+        genvec.disable(handles[1]);
+        assert_eq!(genvec.copied().collect::<Vec<_>>(), genvec.cloned_values());
+        assert_eq!(genvec.copied().collect::<Vec<_>>(), vec![0, 1, 2, 3]);
+        assert_eq!(genvec.live_windows(2).count(), 3);
+        genvec.for_each_mut_with(&10, |offset, x| *x += *offset);
+        assert_eq!(genvec.get_ref(handles[1]), Some(&11));
+        assert_eq!(genvec.iter().copied().collect::<Vec<_>>(), vec![10, 12, 13]);
+    }
+}